@@ -1,8 +1,155 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, RETRY_AFTER, USER_AGENT};
 use reqwest::StatusCode;
+use serde_json::Value;
+use std::fmt;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
-use crate::config::Config;
+use crate::config::{Config, HttpSettings};
 use crate::parser::Command;
+use crate::retry;
+use crate::tls;
+
+/// Why a command did not reach the hub, or what the hub said about it.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Command error: 401 Unauthorized - Invalid API key")]
+    Unauthorized,
+    /// Any other 4xx: the hub understood the request and refused it
+    #[error("Command error: {status} - {body}")]
+    BadRequest { status: u16, body: String, retry_after: Option<Duration> },
+    #[error("Server error: {status} - {body}")]
+    Server { status: u16, body: String, retry_after: Option<Duration> },
+    #[error("{} timed out", if .0.is_connect() { "Connecting to hub" } else { "Request to hub" })]
+    Timeout(#[source] reqwest::Error),
+    #[error("Failed to connect to hub: {}", root_cause(.0))]
+    Connect(#[source] reqwest::Error),
+    #[error("TLS error talking to hub: {}", root_cause(.0))]
+    Tls(#[source] reqwest::Error),
+    /// The response arrived but its body could not be read
+    #[error("Failed to read hub response: {}", root_cause(.0))]
+    Decode(#[source] reqwest::Error),
+    /// A status the hub does not send, such as a redirect
+    #[error("Unexpected response: {status}")]
+    Unexpected { status: u16 },
+    /// The command has no request body, e.g. a multi-node command that was not expanded
+    #[error("Cannot send command: {0:#}")]
+    Encode(anyhow::Error),
+}
+
+impl ClientError {
+    /// What the user can do about the error.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ClientError::Unauthorized => "Check api-key in the config file",
+            ClientError::BadRequest { .. } => "The hub rejected the command; check its parameters, and wire-dialect if the hub names them differently",
+            ClientError::Server { .. } => "The hub failed to handle the command; try again later or check the hub's logs",
+            ClientError::Timeout(e) if e.is_connect() => "Check hub-url and the proxy settings, or raise http.connect-timeout-secs for a slow link",
+            ClientError::Timeout(_) => "The hub did not answer in time; check that it is running and not overloaded, or raise http.timeout-secs",
+            ClientError::Connect(_) => "Check hub-url in the config file and that the hub is running and reachable",
+            ClientError::Tls(_) => "Check the hub's certificate and the [tls] settings, or that hub-url uses http:// for a hub without TLS",
+            ClientError::Decode(_) => "The connection broke while reading the response; try again",
+            ClientError::Unexpected { .. } => "Check that hub-url points at the telemetry hub itself, without the /command suffix",
+            ClientError::Encode(_) => "This is a bug in the CLI; please report it together with the command",
+        }
+    }
+
+    /// Whether sending the command again is safe and may help. Failures where the hub
+    /// cannot have acted on the request (no connection, 429, 502, 503) are always retried;
+    /// failures where it might have are retried only for `idempotent` commands. That includes
+    /// 504: the gateway gave up waiting, but the hub behind it may still have queued the command.
+    pub fn is_retryable(&self, idempotent: bool) -> bool {
+        match self {
+            ClientError::Connect(e) | ClientError::Timeout(e) => e.is_connect() || idempotent,
+            ClientError::BadRequest { status, .. } => *status == StatusCode::TOO_MANY_REQUESTS.as_u16(),
+            ClientError::Server { status: 502 | 503, .. } => true,
+            ClientError::Server { .. } | ClientError::Decode(_) => idempotent,
+            ClientError::Unauthorized | ClientError::Tls(_) | ClientError::Unexpected { .. } | ClientError::Encode(_) => false,
+        }
+    }
+
+    /// The wait the hub asked for with `Retry-After`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClientError::BadRequest { retry_after, .. } | ClientError::Server { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Classifies an error from sending the request.
+    fn from_send(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ClientError::Timeout(e)
+        } else if is_tls(&e) {
+            ClientError::Tls(e)
+        } else {
+            ClientError::Connect(e)
+        }
+    }
+
+    /// Classifies a non-success response.
+    fn from_status(status: StatusCode, body: String, retry_after: Option<Duration>) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ClientError::Unauthorized,
+            status if status.is_client_error() => ClientError::BadRequest { status: status.as_u16(), body, retry_after },
+            status if status.is_server_error() => ClientError::Server { status: status.as_u16(), body, retry_after },
+            status => ClientError::Unexpected { status: status.as_u16() },
+        }
+    }
+}
+
+/// The innermost error message, which names the actual problem ("Connection refused")
+/// rather than reqwest's "error sending request for url".
+fn root_cause(e: &(dyn std::error::Error + 'static)) -> String {
+    let mut cause = e;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+/// Whether the TLS handshake failed. The TLS backend is not part of reqwest's API, so this
+/// looks for a `rustls::Error` in the source chain. It arrives wrapped in `io::Error`s, whose
+/// `source` skips the wrapped error, so those are unwrapped with `get_ref` instead.
+fn is_tls(e: &reqwest::Error) -> bool {
+    let mut cause: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(error) = cause {
+        if error.is::<rustls::Error>() {
+            return true;
+        }
+        cause = match error.downcast_ref::<std::io::Error>() {
+            Some(io) => io.get_ref().map(|inner| inner as &(dyn std::error::Error + 'static)),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+/// A failed attempt that is about to be repeated, passed to the progress callback of
+/// `Client::send_command`.
+#[derive(Debug)]
+pub struct Retry<'a> {
+    pub error: &'a ClientError,
+    /// The attempt that failed, counting from 1
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay: Duration,
+}
+
+impl fmt::Display for Retry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}; retrying in {:.1}s (attempt {} of {})",
+            self.error,
+            self.delay.as_secs_f64(),
+            self.attempt + 1,
+            self.max_attempts
+        )
+    }
+}
 
 pub struct Client {
     config: Config,
@@ -11,10 +158,9 @@ pub struct Client {
 
 impl Client {
     pub fn new(config: Config) -> Result<Self> {
-        let http_client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()
-            .context("Failed to create HTTP client")?;
+        let https = config.hub_url.get(..6).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https:"));
+        let tls = tls::client_config(&config.tls, https).context("Invalid [tls] settings")?;
+        let http_client = http_client(&config.http, tls, |name| std::env::var(name).ok()).context("Failed to create HTTP client")?;
         
         Ok(Self {
             config,
@@ -22,38 +168,303 @@ impl Client {
         })
     }
     
-    pub async fn send_command(&self, command: &Command) -> Result<String> {
-        let json_payload = command.to_json()?;
+    /// Sends a command, repeating it as the retry policy allows. `on_retry` is called before
+    /// each wait.
+    pub async fn send_command(&self, command: &Command, mut on_retry: impl FnMut(&Retry)) -> Result<String, ClientError> {
+        // Raw payloads are already written the way the hub should receive them
+        let json_payload = match command {
+            Command::Raw { payload } => payload.clone(),
+            command => self.config.wire_dialect.encode(command.to_json().map_err(ClientError::Encode)?),
+        };
         
+        let policy = self.config.retry;
+        let start = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let error = match self.send_payload(&json_payload).await {
+                Ok(result) => return Ok(result),
+                Err(error) => error,
+            };
+            let delay = match error.is_retryable(command.is_idempotent()) {
+                true => policy.next_delay(attempt, start.elapsed(), error.retry_after(), fastrand::f64()),
+                false => None,
+            };
+            let Some(delay) = delay else {
+                return Err(error);
+            };
+            on_retry(&Retry {
+                error: &error,
+                attempt,
+                max_attempts: policy.max_attempts,
+                delay,
+            });
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+    
+    async fn send_payload(&self, json_payload: &Value) -> Result<String, ClientError> {
         let url = format!("{}/command", self.config.hub_url);
         
         let response = self.http_client
             .post(&url)
             .header("Content-Type", "application/json")
             .header("X-Api-Key", &self.config.api_key)
-            .json(&json_payload)
+            .json(json_payload)
             .send()
             .await
-            .context("Failed to send request to hub")?;
+            .map_err(ClientError::from_send)?;
         
         let status = response.status();
-        
-        match status {
-            StatusCode::OK => Ok("OK".to_string()),
-            StatusCode::UNAUTHORIZED => {
-                Err(anyhow!("Command error: 401 Unauthorized - Invalid API key"))
-            }
-            status if status.is_client_error() => {
-                let body = response.text().await.unwrap_or_default();
-                Err(anyhow!("Command error: {} - {}", status.as_u16(), body))
-            }
-            status if status.is_server_error() => {
-                let body = response.text().await.unwrap_or_default();
-                Err(anyhow!("Server error: {} - {}", status.as_u16(), body))
+        if status == StatusCode::OK {
+            return Ok("OK".to_string());
+        }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| retry::parse_retry_after(value, Utc::now()));
+        let body = response.text().await.map_err(ClientError::Decode)?;
+        Err(ClientError::from_status(status, body, retry_after))
+    }
+}
+
+/// Builds the HTTP client from the `[http]` settings and their command-line overrides, and
+/// the TLS setup from `tls::client_config`. `env` looks up the proxy environment variables.
+fn http_client(settings: &HttpSettings, tls: rustls::ClientConfig, env: impl Fn(&str) -> Option<String>) -> Result<reqwest::Client> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &settings.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).with_context(|| format!("Invalid header name {:?}", name))?;
+        if name == USER_AGENT {
+            bail!("Set the user agent with user-agent rather than as a header");
+        }
+        if [CONTENT_TYPE, HeaderName::from_static("x-api-key")].contains(&name) {
+            bail!("Header {} is set by the CLI and cannot be overridden", name);
+        }
+        let value = HeaderValue::from_str(value).with_context(|| format!("Invalid value of header {}", name))?;
+        headers.insert(name, value);
+    }
+    let user_agent = settings.user_agent.as_deref().unwrap_or(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")));
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(settings.timeout_secs))
+        .user_agent(user_agent)
+        .default_headers(headers)
+        .use_preconfigured_tls(tls);
+    if let Some(secs) = settings.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    let no_proxy = reqwest::NoProxy::from_string(&settings.no_proxy.join(","));
+    match &settings.proxy {
+        Some(url) => {
+            let proxy = reqwest::Proxy::all(url.as_str()).with_context(|| format!("Invalid proxy URL {}", url))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy));
+        }
+        // Any proxy set here replaces the environment's, so those are set again with the list
+        None if no_proxy.is_some() => {
+            for (scheme, url) in env_proxies(env) {
+                let proxy = match scheme {
+                    "https" => reqwest::Proxy::https(url.as_str()),
+                    _ => reqwest::Proxy::http(url.as_str()),
+                };
+                let proxy = proxy.with_context(|| format!("Invalid proxy URL {} in the environment", url))?;
+                builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
             }
-            _ => {
-                Err(anyhow!("Unexpected response: {}", status.as_u16()))
+        }
+        None => {}
+    }
+    Ok(builder.build()?)
+}
+
+/// The proxy for each scheme from `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` (or their
+/// lowercase forms), as `reqwest` would use them. `env` looks up a variable.
+fn env_proxies(env: impl Fn(&str) -> Option<String>) -> Vec<(&'static str, String)> {
+    [("https", ["https_proxy", "HTTPS_PROXY"]), ("http", ["http_proxy", "HTTP_PROXY"])]
+        .into_iter()
+        .filter_map(|(scheme, names)| {
+            let url = names.into_iter().chain(["all_proxy", "ALL_PROXY"]).find_map(|name| env(name).filter(|url| !url.is_empty()))?;
+            Some((scheme, url))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn client(hub_url: &str) -> Client {
+        let config = format!("api-key = \"k\"\nhub-url = \"{}\"\n[retry]\nbase-delay-ms = 1\njitter = 0.0\n", hub_url);
+        Client::new(toml::from_str(&config).unwrap()).unwrap()
+    }
+
+    /// Answers one request per entry of `responses`, in order, and returns the hub URL to use.
+    async fn serve(responses: &[&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responses = responses.to_vec();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(response.as_bytes()).await;
             }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Answers one request with `OK` and hands over the request as received.
+    async fn capture() -> (String, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let len = stream.read(&mut request).await.unwrap();
+            let _ = stream.write_all(OK.as_bytes()).await;
+            let _ = sender.send(String::from_utf8_lossy(&request[..len]).to_lowercase());
+        });
+        (format!("http://{}", addr), receiver)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+    const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\nretry-after: 0\r\ncontent-length: 4\r\n\r\nbusy";
+    const FAILED: &str = "HTTP/1.1 500 Internal Server Error\r\nconnection: close\r\ncontent-length: 4\r\n\r\noops";
+
+    async fn send(hub_url: &str) -> Result<String, ClientError> {
+        client(hub_url).send_command(&Command::UpdateNode { node_id: Some(21.into()) }, |_| {}).await
+    }
+
+    #[test]
+    fn test_status_classification() {
+        let error = |code: u16| ClientError::from_status(StatusCode::from_u16(code).unwrap(), "unknown node".to_string(), None);
+        assert!(matches!(error(401), ClientError::Unauthorized));
+        assert!(matches!(error(404), ClientError::BadRequest { status: 404, .. }));
+        assert!(matches!(error(503), ClientError::Server { status: 503, .. }));
+        assert!(matches!(error(302), ClientError::Unexpected { status: 302 }));
+
+        assert_eq!(error(401).to_string(), "Command error: 401 Unauthorized - Invalid API key");
+        assert_eq!(error(400).to_string(), "Command error: 400 - unknown node");
+        assert_eq!(error(500).to_string(), "Server error: 500 - unknown node");
+        assert_eq!(error(401).hint(), "Check api-key in the config file");
+
+        for (code, idempotent, retryable) in [(503, false, true), (502, false, true), (504, false, false), (504, true, true), (429, false, true), (500, false, false), (500, true, true), (400, true, false), (401, true, false)] {
+            assert_eq!(error(code).is_retryable(idempotent), retryable, "{} {}", code, idempotent);
         }
     }
+
+    #[tokio::test]
+    async fn test_send_classifies_responses() {
+        assert_eq!(send(&serve(&[OK]).await).await.unwrap(), "OK");
+        let err = send(&serve(&["HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n"]).await).await.unwrap_err();
+        assert!(matches!(err, ClientError::Unauthorized));
+        let err = send(&serve(&["HTTP/1.1 400 Bad Request\r\ncontent-length: 12\r\n\r\nunknown node"]).await).await.unwrap_err();
+        assert!(matches!(err, ClientError::BadRequest { status: 400, ref body, .. } if body == "unknown node"));
+        // A body cut short by the hub
+        let err = send(&serve(&["HTTP/1.1 500 Internal Server Error\r\ncontent-length: 100\r\n\r\npartial"]).await).await.unwrap_err();
+        assert!(matches!(err, ClientError::Decode(_)));
+    }
+
+    #[tokio::test]
+    async fn test_send_classifies_connection_failures() {
+        // Nothing listens on a port that was just released
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let mut retries = 0;
+        let err = client(&format!("http://{}", addr)).send_command(&Command::UpdateNode { node_id: None }, |_| retries += 1).await.unwrap_err();
+        assert!(matches!(err, ClientError::Connect(_)), "{:?}", err);
+        assert!(err.to_string().starts_with("Failed to connect to hub: "));
+        assert_eq!(retries, 3);
+
+        // A plain HTTP server does not answer the TLS handshake
+        let url = serve(&[OK]).await;
+        let err = send(&url.replace("http://", "https://")).await.unwrap_err();
+        assert!(matches!(err, ClientError::Tls(_)), "{:?}", err);
+
+        let err = client("http://127.0.0.1:1").send_command(&Command::Help { name: None }, |_| {}).await.unwrap_err();
+        assert!(matches!(err, ClientError::Encode(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_http_settings() {
+        let settings = |extra: &str| -> HttpSettings { toml::from_str(extra).unwrap() };
+        let with_http = |hub_url: &str, http: HttpSettings| {
+            let mut config: Config = toml::from_str(&format!("api-key = \"k\"\nhub-url = \"{}\"\n", hub_url)).unwrap();
+            config.http = http;
+            Client::new(config)
+        };
+
+        let (url, request) = capture().await;
+        let client = with_http(&url, settings("user-agent = \"station-7\"\n[headers]\nX-Station = \"roof\"\n")).unwrap();
+        client.send_command(&Command::UpdateNode { node_id: Some(21.into()) }, |_| {}).await.unwrap();
+        let request = request.await.unwrap();
+        assert!(request.contains("\r\nuser-agent: station-7\r\n"), "{}", request);
+        assert!(request.contains("\r\nx-station: roof\r\n"), "{}", request);
+        assert!(request.contains("\r\nx-api-key: k\r\n"), "{}", request);
+
+        let (url, request) = capture().await;
+        with_http("http://hub.invalid", settings(&format!("proxy = \"{}\"\n", url))).unwrap()
+            .send_command(&Command::UpdateNode { node_id: Some(21.into()) }, |_| {}).await.unwrap();
+        let request = request.await.unwrap();
+        assert!(request.starts_with("post http://hub.invalid/command "), "{}", request);
+        assert!(request.contains(concat!("\r\nuser-agent: moonblokz-telemetry-cli/", env!("CARGO_PKG_VERSION"), "\r\n")), "{}", request);
+
+        for (http, message) in [
+            ("[headers]\n\"X Station\" = \"roof\"\n", "Invalid header name \"X Station\""),
+            ("[headers]\nX-Api-Key = \"other\"\n", "Header x-api-key is set by the CLI and cannot be overridden"),
+            ("[headers]\nUser-Agent = \"other\"\n", "Set the user agent with user-agent rather than as a header"),
+        ] {
+            let err = with_http("http://127.0.0.1:1", settings(http)).err().unwrap();
+            let err = format!("{:#}", err);
+            assert!(err.starts_with(&format!("Failed to create HTTP client: {}", message)), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_proxy_applies_to_environment_proxy() {
+        let post = |http: HttpSettings, proxy: String| async move {
+            let env = |name: &str| (name == "HTTP_PROXY").then(|| proxy.clone());
+            let client = http_client(&http, tls::client_config(&Default::default(), false).unwrap(), env).unwrap();
+            client.post("http://hub.invalid/command").send().await.map(|_| ())
+        };
+        let (proxy, request) = capture().await;
+        post(HttpSettings::default(), proxy).await.unwrap();
+        assert!(request.await.unwrap().starts_with("post http://hub.invalid/command "));
+
+        // With hub.invalid excluded, the request skips the proxy and fails to resolve the name
+        let (proxy, request) = capture().await;
+        let http = HttpSettings { no_proxy: vec!["hub.invalid".to_string()], ..Default::default() };
+        assert!(post(http, proxy).await.is_err());
+        drop(request);
+
+        assert_eq!(env_proxies(|name| (name == "ALL_PROXY").then(|| "http://all:3128".to_string())), [("https", "http://all:3128".to_string()), ("http", "http://all:3128".to_string())]);
+        assert_eq!(env_proxies(|name| (name == "https_proxy").then(|| "http://tls:3128".to_string())), [("https", "http://tls:3128".to_string())]);
+        assert_eq!(env_proxies(|name| (name == "HTTP_PROXY").then(String::new)), []);
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let mut notices = Vec::new();
+        let url = serve(&[UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let result = client(&url).send_command(&Command::UpdateNode { node_id: None }, |retry| notices.push(retry.to_string())).await;
+        assert_eq!(result.unwrap(), "OK");
+        assert_eq!(notices, [
+            "Server error: 503 - busy; retrying in 0.0s (attempt 2 of 4)",
+            "Server error: 503 - busy; retrying in 0.0s (attempt 3 of 4)",
+        ]);
+
+        // The hub may have acted on a 500, so only commands that are safe to repeat are retried
+        let url = serve(&[FAILED, OK]).await;
+        assert!(matches!(send(&url).await.unwrap_err(), ClientError::Server { status: 500, .. }));
+        let url = serve(&[FAILED, OK]).await;
+        let level = Command::SetLogLevel { node_id: None, log_level: "DEBUG".to_string() };
+        assert_eq!(client(&url).send_command(&level, |_| {}).await.unwrap(), "OK");
+
+        let url = serve(&[UNAVAILABLE, UNAVAILABLE, UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let err = send(&url).await.unwrap_err();
+        assert!(matches!(err, ClientError::Server { status: 503, retry_after: Some(Duration::ZERO), .. }));
+    }
 }
@@ -0,0 +1,160 @@
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::Helper;
+
+use crate::registry::{ParamType, Registry, KEYWORDS};
+use crate::usb;
+
+/// Tab completion for the interactive prompt: command names, parameter names, and the values
+/// of `run_command` (from the USB catalog) and of choice parameters such as `log_level`.
+pub struct LineHelper<'a> {
+    pub registry: &'a Registry,
+}
+
+impl Completer for LineHelper<'_> {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete(line, pos, self.registry))
+    }
+}
+
+impl Hinter for LineHelper<'_> {
+    type Hint = String;
+}
+
+impl Highlighter for LineHelper<'_> {}
+
+impl Validator for LineHelper<'_> {}
+
+impl Helper for LineHelper<'_> {}
+
+/// Candidates for the word that ends at `pos`, and where that word starts.
+pub fn complete(line: &str, pos: usize, registry: &Registry) -> (usize, Vec<Pair>) {
+    let before = &line[..pos];
+    let start = last_outside_quotes(before, &[';']).map_or(0, |idx| idx + 1);
+    let statement = &before[start..];
+    let Some(paren) = statement.find('(') else {
+        return complete_words(statement, start, registry);
+    };
+
+    let Some(spec) = registry.find(statement[..paren].trim()) else {
+        return (pos, Vec::new());
+    };
+    let args = &statement[paren + 1..];
+    let arg_start = last_outside_quotes(args, &[',']).map_or(0, |idx| idx + 1);
+    let index = args[..arg_start].matches(',').count();
+    let arg = &args[arg_start..];
+    let offset = start + paren + 1 + arg_start;
+    let (key, value, value_start) = match arg.find('=') {
+        Some(eq) => (Some(arg[..eq].trim()), &arg[eq + 1..], offset + eq + 1),
+        None => (spec.positional.get(index).map(String::as_str), arg, offset),
+    };
+    let leading = value.len() - value.trim_start().len();
+    let (value, value_start) = match value.trim_start().strip_prefix('"') {
+        Some(rest) => (rest, value_start + leading + 1),
+        None => (value.trim_start(), value_start + leading),
+    };
+
+    let mut candidates = Vec::new();
+    if let Some(key) = key {
+        if spec.name == "run_command" && key.eq_ignore_ascii_case("command") {
+            candidates.extend(usb::completions(value).into_iter().map(|entry| Pair {
+                display: format!("{:<6} {}", entry.name, entry.description),
+                replacement: entry.name.to_string(),
+            }));
+        } else if let Some(param) = spec.param(key).filter(|param| param.kind == ParamType::Choice) {
+            candidates.extend(matching(param.values.iter().map(String::as_str), value));
+        }
+    }
+    // A bare word may also be the start of a named parameter
+    if !arg.contains('=') {
+        let keys = spec.keys().into_iter().map(|key| format!("{}=", key)).collect::<Vec<_>>();
+        candidates.extend(matching(keys.iter().map(String::as_str), value));
+    }
+    (value_start, candidates)
+}
+
+/// Completes the command name, or the argument of `help` and `usb_commands`.
+fn complete_words(statement: &str, start: usize, registry: &Registry) -> (usize, Vec<Pair>) {
+    let leading = statement.len() - statement.trim_start().len();
+    let words = statement.trim_start();
+    match words.split_once(char::is_whitespace) {
+        Some((keyword, argument)) => {
+            let argument_start = start + statement.len() - argument.trim_start().len();
+            let argument = argument.trim_start();
+            let candidates = match keyword.to_ascii_lowercase().as_str() {
+                "help" => matching(registry.commands.iter().map(|spec| spec.name.as_str()).chain(KEYWORDS.iter().copied()), argument),
+                "usb_commands" => matching(usb::CATALOG.iter().map(|entry| entry.name), argument),
+                _ => Vec::new(),
+            };
+            (argument_start, candidates)
+        }
+        None => {
+            let names = registry.commands.iter().map(|spec| spec.name.as_str()).chain(KEYWORDS.iter().copied());
+            (start + leading, matching(names, words))
+        }
+    }
+}
+
+/// The words starting with `prefix`, ignoring case.
+fn matching<'a>(words: impl Iterator<Item = &'a str>, prefix: &str) -> Vec<Pair> {
+    words
+        .filter(|word| word.len() >= prefix.len() && word.is_char_boundary(prefix.len()) && word[..prefix.len()].eq_ignore_ascii_case(prefix))
+        .map(|word| Pair {
+            display: word.to_string(),
+            replacement: word.to_string(),
+        })
+        .collect()
+}
+
+/// Byte offset of the last of `separators` that is not inside a quoted string.
+fn last_outside_quotes(text: &str, separators: &[char]) -> Option<usize> {
+    let mut last = None;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (idx, ch) in text.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            _ if !in_quotes && separators.contains(&ch) => last = Some(idx),
+            _ => {}
+        }
+    }
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replacements(line: &str) -> (usize, Vec<String>) {
+        let (start, candidates) = complete(line, line.len(), Registry::builtin());
+        (start, candidates.into_iter().map(|pair| pair.replacement).collect())
+    }
+
+    #[test]
+    fn test_complete_usb_commands() {
+        assert_eq!(replacements("run_command(node_id=21, command=/L"), (32, vec!["/LT".into(), "/LD".into(), "/LI".into(), "/LW".into(), "/LE".into()]));
+        assert_eq!(replacements("run_command(21, \"/b"), (17, vec!["/BS".into()]));
+        assert_eq!(replacements("command(node_id=21, command=/x").1, Vec::<String>::new());
+        let (_, candidates) = complete("run_command(21, /B", 18, Registry::builtin());
+        assert!(candidates[0].display.starts_with("/BS    Reboot into the bootloader"));
+        assert_eq!(replacements("usb_commands /l").1.len(), 5);
+    }
+
+    #[test]
+    fn test_complete_names_and_parameters() {
+        assert_eq!(replacements("set_log"), (0, vec!["set_log_level".into(), "set_log_filter".into()]));
+        assert_eq!(replacements("update_node(21); up"), (17, vec!["update_node".into(), "update_probe".into()]));
+        assert_eq!(replacements("help reb"), (5, vec!["reboot_probe".into()]));
+        assert_eq!(replacements("set_log_level(node_id=21, log_level=w"), (36, vec!["WARN".into()]));
+        assert_eq!(replacements("set_log_level(21, log"), (18, vec!["log_level=".into()]));
+        // A `;` inside a quoted value does not start a new command
+        assert_eq!(replacements("set_log_filter(log_filter=\"a;b\", no").1, vec!["node_id=".to_string()]);
+        assert_eq!(replacements("bogus(x").1, Vec::<String>::new());
+    }
+}
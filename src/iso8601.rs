@@ -0,0 +1,344 @@
+use chrono::{Days, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, Utc, Weekday};
+
+use crate::parser::Span;
+
+/// A timestamp as written: the wall-clock time and its UTC offset, if one was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub local: NaiveDateTime,
+    pub offset: Option<FixedOffset>,
+}
+
+/// The part of the input that does not fit ISO 8601 and the form expected there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iso8601Error {
+    pub expected: &'static str,
+    /// Byte range into the input
+    pub span: Span,
+}
+
+const DATE_FORMS: &str = "a date (YYYY-MM-DD, YYYY-Www-D or YYYY-DDD)";
+const OFFSET_FORMS: &str = "a UTC offset (Z, +01, +01:00 or +0100)";
+const WEEKDAYS: [Weekday; 7] = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun];
+
+/// Parses an ISO 8601 date and time of day with an optional UTC offset.
+///
+/// Dates are calendar (`2025-10-23`), week (`2025-W43-4`) or ordinal (`2025-296`) dates,
+/// times are `hh:mm[:ss[.fff]]`, and both may use the basic format without separators
+/// (`20251023T1530Z`). Offsets are `Z`, `±hh`, `±hh:mm` or `±hhmm`.
+pub fn parse(text: &str) -> Result<Timestamp, Iso8601Error> {
+    let mut cursor = Cursor { text, pos: 0 };
+    let date = date(&mut cursor)?;
+
+    if !cursor.eat(|c| matches!(c, 'T' | 't' | ' ')) {
+        let expected = if cursor.at_end() { "a time after the date (T15:30)" } else { "'T' between the date and the time" };
+        return Err(cursor.error(expected));
+    }
+    let (time, end_of_day) = time(&mut cursor)?;
+    let offset = if cursor.at_end() { None } else { Some(offset(&mut cursor)?) };
+    if !cursor.at_end() {
+        return Err(Iso8601Error {
+            expected: "nothing after the UTC offset",
+            span: cursor.pos..text.len(),
+        });
+    }
+
+    let mut local = date.and_time(time);
+    if end_of_day {
+        local = local.checked_add_days(Days::new(1)).ok_or(Iso8601Error {
+            expected: "a date before the end of the supported range",
+            span: 0..text.len(),
+        })?;
+    }
+    Ok(Timestamp { local, offset })
+}
+
+struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.text.len()
+    }
+
+    fn eat(&mut self, accept: impl Fn(char) -> bool) -> bool {
+        match self.peek() {
+            Some(c) if accept(c) => {
+                self.pos += c.len_utf8();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn digits_ahead(&self) -> usize {
+        self.text[self.pos..].bytes().take_while(u8::is_ascii_digit).count()
+    }
+
+    /// Error at the next character, or at the end of the input.
+    fn error(&self, expected: &'static str) -> Iso8601Error {
+        let len = self.peek().map_or(0, char::len_utf8);
+        Iso8601Error {
+            expected,
+            span: self.pos..self.pos + len,
+        }
+    }
+
+    /// Reads exactly `width` digits and checks the value is in `range`.
+    fn field(&mut self, width: usize, expected: &'static str, range: std::ops::RangeInclusive<u32>) -> Result<u32, Iso8601Error> {
+        let start = self.pos;
+        if self.digits_ahead() < width {
+            return Err(self.error(expected));
+        }
+        self.pos += width;
+        match self.text[start..self.pos].parse() {
+            Ok(value) if range.contains(&value) => Ok(value),
+            _ => Err(Iso8601Error {
+                expected,
+                span: start..self.pos,
+            }),
+        }
+    }
+
+    fn expect(&mut self, accept: impl Fn(char) -> bool, expected: &'static str) -> Result<(), Iso8601Error> {
+        if self.eat(accept) {
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+}
+
+/// Hyphens as typeset in some documents count as the ASCII hyphen.
+fn is_hyphen(c: char) -> bool {
+    matches!(c, '-' | '\u{2010}' | '\u{2011}')
+}
+
+fn date(cursor: &mut Cursor) -> Result<NaiveDate, Iso8601Error> {
+    if cursor.digits_ahead() < 4 {
+        // Nothing here looks like a date, so the whole value is wrong
+        return Err(Iso8601Error {
+            expected: DATE_FORMS,
+            span: 0..cursor.text.len(),
+        });
+    }
+    let year = cursor.field(4, DATE_FORMS, 0..=9999)? as i32;
+    let extended = cursor.eat(is_hyphen);
+
+    if cursor.eat(|c| matches!(c, 'W' | 'w')) {
+        let week_start = cursor.pos;
+        let week = cursor.field(2, "a week number (Www, W01-W53)", 1..=53)?;
+        if extended {
+            cursor.expect(is_hyphen, "'-' before the weekday (YYYY-Www-D)")?;
+        }
+        let weekday = cursor.field(1, "a weekday (D, 1 for Monday to 7 for Sunday)", 1..=7)?;
+        return NaiveDate::from_isoywd_opt(year, week, WEEKDAYS[weekday as usize - 1]).ok_or(Iso8601Error {
+            expected: "a week number that exists in that year",
+            span: week_start..week_start + 2,
+        });
+    }
+
+    let digits = cursor.digits_ahead();
+    if digits == 3 {
+        let day_start = cursor.pos;
+        let ordinal = cursor.field(3, "a day of the year (DDD, 001-366)", 1..=366)?;
+        return NaiveDate::from_yo_opt(year, ordinal).ok_or(Iso8601Error {
+            expected: "a day of the year that exists in that year",
+            span: day_start..cursor.pos,
+        });
+    }
+    if !extended && digits != 4 {
+        return Err(cursor.error(DATE_FORMS));
+    }
+
+    let month = cursor.field(2, "a month (MM, 01-12)", 1..=12)?;
+    if extended {
+        cursor.expect(is_hyphen, "'-' between the month and the day (YYYY-MM-DD)")?;
+    }
+    let day_start = cursor.pos;
+    let day = cursor.field(2, "a day (DD, 01-31)", 1..=31)?;
+    NaiveDate::from_ymd_opt(year, month, day).ok_or(Iso8601Error {
+        expected: "a day that exists in that month",
+        span: day_start..cursor.pos,
+    })
+}
+
+/// Reads a time of day; `24:00` is returned as midnight with the end-of-day flag set.
+fn time(cursor: &mut Cursor) -> Result<(NaiveTime, bool), Iso8601Error> {
+    let hour_start = cursor.pos;
+    let hour = cursor.field(2, "an hour (hh, 00-24)", 0..=24)?;
+    let extended = cursor.eat(|c| c == ':');
+    let minute = cursor.field(2, "minutes (mm, 00-59)", 0..=59)?;
+
+    let (mut second, mut nano) = (0, 0);
+    if (extended && cursor.eat(|c| c == ':')) || (!extended && cursor.digits_ahead() > 0) {
+        second = cursor.field(2, "seconds (ss, 00-59)", 0..=59)?;
+        if cursor.eat(|c| matches!(c, '.' | ',')) {
+            nano = fraction(cursor)?;
+        }
+    }
+
+    if hour == 24 {
+        if minute != 0 || second != 0 || nano != 0 {
+            return Err(Iso8601Error {
+                expected: "an hour (hh, 00-23); 24 is only allowed as 24:00",
+                span: hour_start..cursor.pos,
+            });
+        }
+        return Ok((NaiveTime::MIN, true));
+    }
+    let time = NaiveTime::from_hms_nano_opt(hour, minute, second, nano).ok_or(Iso8601Error {
+        expected: "a time of day (hh:mm:ss)",
+        span: hour_start..cursor.pos,
+    })?;
+    Ok((time, false))
+}
+
+/// Reads the digits after the decimal point as nanoseconds; digits past the ninth are dropped.
+fn fraction(cursor: &mut Cursor) -> Result<u32, Iso8601Error> {
+    let digits = cursor.digits_ahead();
+    if digits == 0 {
+        return Err(cursor.error("digits after the decimal point"));
+    }
+    let kept = &cursor.text[cursor.pos..cursor.pos + digits.min(9)];
+    cursor.pos += digits;
+    let nano = kept.bytes().fold(0, |acc, b| acc * 10 + u32::from(b - b'0'));
+    Ok(nano * 10u32.pow(9 - kept.len() as u32))
+}
+
+fn offset(cursor: &mut Cursor) -> Result<FixedOffset, Iso8601Error> {
+    let sign = if cursor.eat(|c| matches!(c, 'Z' | 'z')) {
+        return Ok(Utc.fix());
+    } else if cursor.eat(|c| c == '+') {
+        1
+    } else if cursor.eat(|c| c == '-' || c == '\u{2212}') {
+        -1
+    } else {
+        return Err(cursor.error(OFFSET_FORMS));
+    };
+
+    let start = cursor.pos;
+    let hours = cursor.field(2, "offset hours (hh, 00-23)", 0..=23)?;
+    let minutes = if cursor.eat(|c| c == ':') || cursor.digits_ahead() > 0 {
+        cursor.field(2, "offset minutes (mm, 00-59)", 0..=59)?
+    } else {
+        0
+    };
+    let seconds = (hours * 3600 + minutes * 60) as i32;
+    FixedOffset::east_opt(sign * seconds).ok_or(Iso8601Error {
+        expected: OFFSET_FORMS,
+        span: start..cursor.pos,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepted forms and the timestamp they denote, written as RFC 3339 (or without an
+    /// offset for local times).
+    const VALID: &[(&str, &str)] = &[
+        // README, DEVELOPER.md, EXAMPLES.md and the spec
+        ("2025-10-23T15:30+01", "2025-10-23T15:30:00+01:00"),
+        ("2025-10-23T18:00+01", "2025-10-23T18:00:00+01:00"),
+        ("2025-10-23T15:30:00+01:00", "2025-10-23T15:30:00+01:00"),
+        ("2025-10-23T15:00:00+01:00", "2025-10-23T15:00:00+01:00"),
+        ("2025-10-23T15:30:00Z", "2025-10-23T15:30:00+00:00"),
+        ("2025-10-23T18:00:00Z", "2025-10-23T18:00:00+00:00"),
+        ("2026-10-23T15:30", "2026-10-23T15:30:00"),
+        // The spec is typeset with non-breaking hyphens
+        ("2025\u{2011}10\u{2011}23T15:30+01", "2025-10-23T15:30:00+01:00"),
+        // Offsets
+        ("2025-10-23T15:30Z", "2025-10-23T15:30:00+00:00"),
+        ("2025-10-23T15:30z", "2025-10-23T15:30:00+00:00"),
+        ("2025-10-23T15:30-05", "2025-10-23T15:30:00-05:00"),
+        ("2025-10-23T15:30+0530", "2025-10-23T15:30:00+05:30"),
+        ("2025-10-23T15:30+05:45", "2025-10-23T15:30:00+05:45"),
+        ("2025-10-23T15:30\u{2212}03:00", "2025-10-23T15:30:00-03:00"),
+        ("2025-10-23T15:30-00:00", "2025-10-23T15:30:00+00:00"),
+        // Seconds and fractions
+        ("2025-10-23T15:30:45.5Z", "2025-10-23T15:30:45.500+00:00"),
+        ("2025-10-23T15:30:45,25Z", "2025-10-23T15:30:45.250+00:00"),
+        ("2025-10-23T15:30:45.123456789Z", "2025-10-23T15:30:45.123456789+00:00"),
+        ("2025-10-23T15:30:45.1234567891Z", "2025-10-23T15:30:45.123456789+00:00"),
+        ("2025-10-23t15:30:00Z", "2025-10-23T15:30:00+00:00"),
+        ("2025-10-23 15:30:00Z", "2025-10-23T15:30:00+00:00"),
+        ("2025-10-23T24:00Z", "2025-10-24T00:00:00+00:00"),
+        // Basic format
+        ("20251023T1530Z", "2025-10-23T15:30:00+00:00"),
+        ("20251023T153045+0100", "2025-10-23T15:30:45+01:00"),
+        ("20251023T153045.25+01", "2025-10-23T15:30:45.250+01:00"),
+        ("20251023T1530", "2025-10-23T15:30:00"),
+        // Week and ordinal dates
+        ("2025-W43-4T15:30Z", "2025-10-23T15:30:00+00:00"),
+        ("2025W434T1530Z", "2025-10-23T15:30:00+00:00"),
+        ("2026-W53-5T00:00Z", "2027-01-01T00:00:00+00:00"),
+        ("2025-296T15:30Z", "2025-10-23T15:30:00+00:00"),
+        ("2025296T1530Z", "2025-10-23T15:30:00+00:00"),
+        ("2024-366T00:00Z", "2024-12-31T00:00:00+00:00"),
+    ];
+
+    /// Rejected forms, the expected form named in the error and the text it points at.
+    const INVALID: &[(&str, &str, &str)] = &[
+        ("invalid", "a date (YYYY-MM-DD, YYYY-Www-D or YYYY-DDD)", "invalid"),
+        ("25-10-23T15:30Z", "a date (YYYY-MM-DD, YYYY-Www-D or YYYY-DDD)", "25-10-23T15:30Z"),
+        ("2025-10-23", "a time after the date (T15:30)", ""),
+        ("2025-10-23_15:30Z", "'T' between the date and the time", "_"),
+        ("2025-13-01T00:00Z", "a month (MM, 01-12)", "13"),
+        ("2025-1-23T00:00Z", "a month (MM, 01-12)", "1"),
+        ("2025-10/23T00:00Z", "'-' between the month and the day (YYYY-MM-DD)", "/"),
+        ("2025-02-30T00:00Z", "a day that exists in that month", "30"),
+        ("2025-10-32T00:00Z", "a day (DD, 01-31)", "32"),
+        ("202510T00:00Z", "a date (YYYY-MM-DD, YYYY-Www-D or YYYY-DDD)", "1"),
+        ("2025-W54-1T00:00Z", "a week number (Www, W01-W53)", "54"),
+        ("2025-W53-1T00:00Z", "a week number that exists in that year", "53"),
+        ("2025-W43-8T00:00Z", "a weekday (D, 1 for Monday to 7 for Sunday)", "8"),
+        ("2025-W434T00:00Z", "'-' before the weekday (YYYY-Www-D)", "4"),
+        ("2025-366T00:00Z", "a day of the year that exists in that year", "366"),
+        ("2025-10-23T25:00Z", "an hour (hh, 00-24)", "25"),
+        ("2025-10-23T5:30Z", "an hour (hh, 00-24)", "5"),
+        ("2025-10-23T15Z", "minutes (mm, 00-59)", "Z"),
+        ("2025-10-23T15:60Z", "minutes (mm, 00-59)", "60"),
+        ("2025-10-23T15:30:61Z", "seconds (ss, 00-59)", "61"),
+        ("2025-10-23T15:30:00.Z", "digits after the decimal point", "Z"),
+        ("2025-10-23T24:30Z", "an hour (hh, 00-23); 24 is only allowed as 24:00", "24:30"),
+        ("2025-10-23T15:30+1", "offset hours (hh, 00-23)", "1"),
+        ("2025-10-23T15:30+24", "offset hours (hh, 00-23)", "24"),
+        ("2025-10-23T15:30+01:60", "offset minutes (mm, 00-59)", "60"),
+        ("2025-10-23T15:30 CET", "a UTC offset (Z, +01, +01:00 or +0100)", " "),
+        ("2025-10-23T15:30:00+01:00:00", "nothing after the UTC offset", ":00"),
+        ("2025-10-23T15:30ZZ", "nothing after the UTC offset", "Z"),
+    ];
+
+    fn render(timestamp: Timestamp) -> String {
+        match timestamp.offset {
+            Some(offset) => timestamp.local.and_local_timezone(offset).unwrap().to_rfc3339(),
+            None => timestamp.local.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+        }
+    }
+
+    #[test]
+    fn test_valid_forms() {
+        for (input, expected) in VALID {
+            match parse(input) {
+                Ok(timestamp) => assert_eq!(render(timestamp), *expected, "{}", input),
+                Err(e) => panic!("{}: expected {} at {:?}", input, e.expected, e.span),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_forms() {
+        for (input, expected, at) in INVALID {
+            let err = parse(input).expect_err(input);
+            assert_eq!(err.expected, *expected, "{}", input);
+            assert_eq!(&input[err.span.clone()], *at, "{}", input);
+        }
+    }
+}
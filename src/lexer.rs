@@ -0,0 +1,216 @@
+use crate::parser::{ParseError, Span};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Unquoted text; surrounding whitespace is trimmed but inner whitespace is kept
+    Word(String),
+    /// Double-quoted string with quotes removed and escapes resolved
+    Str(String),
+    Eq,
+    Comma,
+    LParen,
+    RParen,
+}
+
+impl TokenKind {
+    /// Short human-readable description used in error messages.
+    pub fn describe(&self) -> String {
+        match self {
+            TokenKind::Word(w) => format!("'{}'", w),
+            TokenKind::Str(s) => format!("\"{}\"", s),
+            TokenKind::Eq => "'='".to_string(),
+            TokenKind::Comma => "','".to_string(),
+            TokenKind::LParen => "'('".to_string(),
+            TokenKind::RParen => "')'".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+fn is_delimiter(ch: char) -> bool {
+    matches!(ch, '=' | ',' | '(' | ')' | '"')
+}
+
+/// Splits `input` into tokens. Spans are byte ranges into `input`.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, ch)) = chars.peek() {
+        let punct = match ch {
+            '=' => Some(TokenKind::Eq),
+            ',' => Some(TokenKind::Comma),
+            '(' => Some(TokenKind::LParen),
+            ')' => Some(TokenKind::RParen),
+            _ => None,
+        };
+
+        if let Some(kind) = punct {
+            chars.next();
+            tokens.push(Token {
+                kind,
+                span: start..start + 1,
+            });
+        } else if ch.is_whitespace() {
+            chars.next();
+        } else if ch == '"' {
+            chars.next();
+            let (value, end) = lex_string(input, start, &mut chars)?;
+            tokens.push(Token {
+                kind: TokenKind::Str(value),
+                span: start..end,
+            });
+        } else {
+            let mut end = start;
+            while let Some(&(idx, c)) = chars.peek() {
+                if is_delimiter(c) {
+                    break;
+                }
+                chars.next();
+                if !c.is_whitespace() {
+                    end = idx + c.len_utf8();
+                }
+            }
+            tokens.push(Token {
+                kind: TokenKind::Word(input[start..end].to_string()),
+                span: start..end,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads the body of a quoted string whose opening quote is at `start`.
+/// Returns the unescaped value and the byte offset just past the closing quote.
+fn lex_string(
+    input: &str,
+    start: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+) -> Result<(String, usize), ParseError> {
+    let mut value = String::new();
+
+    while let Some((idx, ch)) = chars.next() {
+        match ch {
+            '"' => return Ok((value, idx + 1)),
+            '\\' => {
+                let invalid = |end: usize| ParseError::InvalidEscape { span: idx..end };
+                let (esc_idx, esc) = chars.next().ok_or_else(|| invalid(input.len()))?;
+                let esc_end = esc_idx + esc.len_utf8();
+                match esc {
+                    '"' => value.push('"'),
+                    '\\' => value.push('\\'),
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'x' => {
+                        let hex: String = (0..2).filter_map(|_| chars.next_if(|(_, c)| c.is_ascii_hexdigit())).map(|(_, c)| c).collect();
+                        let hex_end = esc_end + hex.len();
+                        // Only ASCII is allowed so that every escape maps to exactly one byte on the wire
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(byte) if hex.len() == 2 && byte.is_ascii() => value.push(byte as char),
+                            _ => return Err(invalid(hex_end)),
+                        }
+                    }
+                    _ => return Err(invalid(esc_end)),
+                }
+            }
+            _ => value.push(ch),
+        }
+    }
+
+    Err(ParseError::UnterminatedString {
+        span: start..input.len(),
+    })
+}
+
+/// Renders `value` as a quoted string that `tokenize` reads back unchanged.
+///
+/// ASCII control characters other than newline and tab are written as `\xNN`.
+pub fn quote(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            _ if ch.is_ascii_control() => out.push_str(&format!("\\x{:02X}", ch as u8)),
+            _ => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        tokenize(input).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize_command() {
+        assert_eq!(
+            kinds("set_log_filter( node_id = 21, log_filter=\"[ERROR]\")"),
+            vec![
+                TokenKind::Word("set_log_filter".to_string()),
+                TokenKind::LParen,
+                TokenKind::Word("node_id".to_string()),
+                TokenKind::Eq,
+                TokenKind::Word("21".to_string()),
+                TokenKind::Comma,
+                TokenKind::Word("log_filter".to_string()),
+                TokenKind::Eq,
+                TokenKind::Str("[ERROR]".to_string()),
+                TokenKind::RParen,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bare_word_keeps_inner_whitespace() {
+        let tokens = tokenize("a=  tomorrow 09:00  ,").unwrap();
+        assert_eq!(tokens[2].kind, TokenKind::Word("tomorrow 09:00".to_string()));
+        assert_eq!(tokens[2].span, 4..18);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(
+            kinds(r#""a\"b\\c\nd\te\x41,()""#),
+            vec![TokenKind::Str("a\"b\\c\nd\teA,()".to_string())]
+        );
+        assert_eq!(kinds(r#""""#), vec![TokenKind::Str(String::new())]);
+    }
+
+    #[test]
+    fn test_quote_round_trips() {
+        let value = "say \"hi\"\\\n\t\r\x07 ünïcode";
+        assert_eq!(quote(value), r#""say \"hi\"\\\n\t\x0D\x07 ünïcode""#);
+        assert_eq!(kinds(&quote(value)), vec![TokenKind::Str(value.to_string())]);
+    }
+
+    #[test]
+    fn test_invalid_escapes() {
+        for (input, escape) in [(r#""\q""#, r"\q"), (r#""\x4""#, r"\x4"), (r#""\xZZ""#, r"\x"), (r#""\xff""#, r"\xff")] {
+            let err = tokenize(input).unwrap_err();
+            assert!(matches!(err, ParseError::InvalidEscape { .. }), "{}", input);
+            assert_eq!(&input[err.span()], escape);
+        }
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        let err = tokenize("log_filter=\"[ERROR]").unwrap_err();
+        assert_eq!(err, ParseError::UnterminatedString { span: 11..19 });
+        assert!(matches!(tokenize(r#""abc\"#).unwrap_err(), ParseError::InvalidEscape { .. }));
+    }
+}
@@ -1,14 +1,42 @@
 mod config;
+mod iso8601;
+mod lexer;
+mod nodes;
 mod parser;
+mod preview;
+mod registry;
+mod retry;
+mod schedule;
+mod script;
+mod time;
+mod tls;
+mod usb;
+mod wire;
 mod client;
+mod completion;
 
 use anyhow::{Context, Result};
+use chrono::Utc;
 use clap::Parser as ClapParser;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 
 use config::Config;
-use parser::{parse_command, Command};
-use client::Client;
+use nodes::NodeSelector;
+use parser::{parse_command_with, Command, ParseContext, ParseError};
+use registry::Registry;
+use retry::RetryPolicy;
+use script::Statement;
+use wire::Dialect;
+use client::{Client, ClientError};
+
+/// What a script does after a command fails.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OnError {
+    /// Skip the rest of the script
+    Stop,
+    /// Carry on with the next command
+    Continue,
+}
 
 #[derive(ClapParser, Debug)]
 #[command(name = "moonblokz-telemetry-cli")]
@@ -21,102 +49,605 @@ struct Args {
     /// Single command to send and exit
     #[arg(long)]
     command: Option<String>,
+    
+    /// Script file with one command per line to run and exit (`-` reads standard input)
+    #[arg(long, value_name = "FILE", conflicts_with = "command")]
+    script: Option<String>,
+    
+    /// JSON payload in the selected dialect to validate and send, e.g. '{"command": "update_node", "parameters": {"node id": 21}}'
+    #[arg(long, value_name = "PAYLOAD", conflicts_with_all = ["command", "script"])]
+    json: Option<String>,
+    
+    /// Hub request body to send as written, without local validation, e.g. '{"command": "set_radio_power", "parameters": {"node id": 21, "dbm": 14}}'
+    #[arg(long, value_name = "PAYLOAD", conflicts_with_all = ["command", "script", "json"])]
+    raw_json: Option<String>,
+    
+    /// JSON naming scheme of the hub, overriding `wire-dialect` in the config file
+    #[arg(long, value_enum)]
+    dialect: Option<Dialect>,
+    
+    /// Print the JSON Schema of the hub's /command request body and exit
+    #[arg(long)]
+    json_schema: bool,
+    
+    /// Send USB commands that are dangerous or not in the catalog without asking
+    #[arg(long)]
+    force: bool,
+    
+    /// Send each request once, ignoring the retry policy in the config file
+    #[arg(long)]
+    no_retry: bool,
+    
+    /// Time limit of each request in seconds, overriding `http.timeout-secs`
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+    
+    /// Time limit of connecting to the hub in seconds, overriding `http.connect-timeout-secs`
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    connect_timeout: Option<u64>,
+    
+    /// Proxy for requests to the hub, overriding `http.proxy`
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+    
+    /// Comma-separated hosts to reach without the proxy, overriding `http.no-proxy`
+    #[arg(long, value_name = "HOSTS", value_delimiter = ',')]
+    no_proxy: Option<Vec<String>>,
+    
+    /// User-Agent header, overriding `http.user-agent`
+    #[arg(long, value_name = "TEXT")]
+    user_agent: Option<String>,
+    
+    /// Extra header for every request, added to `http.headers`; can be repeated
+    #[arg(long, value_name = "NAME: VALUE", value_parser = parse_header)]
+    header: Vec<(String, String)>,
+    
+    /// Allow control characters such as CR and LF in run_command and log_filter values
+    #[arg(long)]
+    allow_control_chars: bool,
+    
+    /// Whether to stop or continue after a failed command in a script or multi-command line
+    #[arg(long, value_enum, default_value_t = OnError::Stop)]
+    on_error: OnError,
+}
+
+/// Reads a `--header` value written as in HTTP, e.g. "X-Station: roof".
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err("expected NAME: VALUE".to_string()),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
     
+    if args.json_schema {
+        println!("{}", serde_json::to_string_pretty(&wire::schema())?);
+        return Ok(());
+    }
+    
     // Load configuration
-    let config = Config::load(&args.config)
+    let mut config = Config::load(&args.config)
         .context(format!("Failed to load configuration from {}", args.config))?;
+    if let Some(dialect) = args.dialect {
+        config.wire_dialect = dialect;
+    }
+    if args.no_retry {
+        config.retry = RetryPolicy::none();
+    }
+    config.http.timeout_secs = args.timeout.unwrap_or(config.http.timeout_secs);
+    config.http.connect_timeout_secs = args.connect_timeout.or(config.http.connect_timeout_secs);
+    config.http.proxy = args.proxy.or(config.http.proxy);
+    config.http.no_proxy = args.no_proxy.unwrap_or(config.http.no_proxy);
+    config.http.user_agent = args.user_agent.or(config.http.user_agent);
+    config.http.headers.extend(args.header);
     
     // Create client
-    let client = Client::new(config)?;
+    let client = Client::new(config.clone())?;
+    let registry = match &config.commands_file {
+        Some(path) => Registry::load(path).with_context(|| format!("Failed to load commands from {}", path.display()))?,
+        None => Registry::builtin(),
+    };
+    let ctx = ParseContext {
+        registry: Some(registry),
+        inventory: &config.nodes,
+        timezone: config.timezone,
+        allow_control_chars: args.allow_control_chars,
+        ..Default::default()
+    };
     
-    // Single command mode or interactive mode
+    // Single command mode, script mode, JSON mode or interactive mode
     if let Some(command_str) = args.command {
         // Single command mode
-        execute_single_command(&client, &command_str).await
+        execute_single_command(&client, &config, &ctx, &command_str, args.on_error, args.force).await
+    } else if let Some(payload) = args.json {
+        // JSON mode
+        execute_json(&client, &config, &ctx, &payload, args.force).await
+    } else if let Some(payload) = args.raw_json {
+        // Raw JSON mode
+        execute_raw_json(&client, &config, &ctx, &payload).await
+    } else if let Some(path) = args.script {
+        // Script mode
+        execute_script(&client, &config, &ctx, &path, args.on_error, args.force).await
     } else {
         // Interactive mode
-        interactive_mode(&client).await
+        interactive_mode(&client, &config, &ctx, args.on_error, args.force).await
     }
 }
 
-async fn execute_single_command(client: &Client, command_str: &str) -> Result<()> {
-    match parse_command(command_str) {
-        Ok(Command::Quit) => {
-            eprintln!("Quit command is only valid in interactive mode");
-            std::process::exit(1);
+/// Prints the inventory, or one entry of it for `nodes show <name>`. Returns false if the node is unknown.
+fn show_nodes(config: &Config, name: Option<&str>) -> bool {
+    let Some(name) = name else {
+        if config.nodes.is_empty() {
+            println!("No nodes in the inventory. Add [[nodes]] entries to the config file.");
+        } else {
+            println!("{}", nodes::format_inventory(&config.nodes));
+        }
+        return true;
+    };
+
+    match nodes::find_node(&config.nodes, name) {
+        Some(node) => {
+            println!("{}", nodes::format_node(node));
+            true
+        }
+        None => {
+            eprintln!("No node named {} in the inventory", name);
+            false
+        }
+    }
+}
+
+/// Prints a client error with its remediation hint.
+fn report_client_error(e: &ClientError) {
+    eprintln!("{}", e);
+    eprintln!("Hint: {}", e.hint());
+}
+
+/// Sends a parsed command, fanning it out into one request per node when it selects
+/// several nodes, and prints the outcome.
+async fn dispatch(client: &Client, config: &Config, command: &Command) -> Outcome {
+    let commands = match command.expand(&config.known_node_ids()) {
+        Ok(commands) => commands,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Outcome::Failed;
+        }
+    };
+
+    if let [command] = commands.as_slice() {
+        return match client.send_command(command, |retry| eprintln!("{}", retry)).await {
+            Ok(result) => {
+                println!("{}", result);
+                Outcome::Ok
+            }
+            Err(e) => {
+                report_client_error(&e);
+                Outcome::from_errors(&[e])
+            }
+        };
+    }
+
+    let mut rows = Vec::with_capacity(commands.len());
+    let mut errors: Vec<ClientError> = Vec::new();
+    for command in &commands {
+        let node = command.nodes().and_then(NodeSelector::single).unwrap_or_default();
+        // Every remaining request would fail the same way
+        if matches!(errors.last(), Some(ClientError::Unauthorized)) {
+            rows.push((node, "skipped".to_string()));
+            continue;
+        }
+        match client.send_command(command, |retry| eprintln!("Node {}: {}", node, retry)).await {
+            Ok(result) => rows.push((node, result)),
+            Err(e) => {
+                rows.push((node, e.to_string()));
+                errors.push(e);
+            }
+        }
+    }
+
+    println!("{:<6} RESULT", "NODE");
+    for (node, result) in &rows {
+        println!("{:<6} {}", node, result);
+    }
+    println!("{}/{} nodes OK", rows.len() - errors.len(), rows.len());
+    // One hint per kind of failure rather than per node, in the order they first occurred
+    let mut hints: Vec<&str> = Vec::new();
+    for hint in errors.iter().map(ClientError::hint) {
+        if !hints.contains(&hint) {
+            hints.push(hint);
+        }
+    }
+    for hint in hints {
+        eprintln!("Hint: {}", hint);
+    }
+
+    Outcome::from_errors(&errors)
+}
+
+/// Number of kept and of dropped lines shown by `preview_filter`.
+const PREVIEW_SAMPLES: usize = 5;
+
+/// Result of running one parsed command.
+enum Outcome {
+    Ok,
+    Failed,
+    Unauthorized,
+    Quit,
+}
+
+impl Outcome {
+    fn from_errors(errors: &[ClientError]) -> Self {
+        if errors.iter().any(|e| matches!(e, ClientError::Unauthorized)) {
+            Outcome::Unauthorized
+        } else if errors.is_empty() {
+            Outcome::Ok
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+async fn execute(client: &Client, config: &Config, registry: &Registry, command: &Command) -> Outcome {
+    match command {
+        Command::Quit => Outcome::Quit,
+        Command::Help { name: None } => {
+            println!("{}", registry.format_help());
+            Outcome::Ok
+        }
+        Command::Help { name: Some(name) } => match registry.describe(name) {
+            Some(help) => {
+                println!("{}", help);
+                Outcome::Ok
+            }
+            None => {
+                let suggestion = parser::closest(name, registry.names().chain(registry::KEYWORDS.iter().copied()));
+                eprintln!("Unknown command {}{}", name, suggestion.map(|s| format!(" (did you mean {}?)", s)).unwrap_or_default());
+                Outcome::Failed
+            }
+        },
+        Command::Nodes { name } => {
+            if show_nodes(config, name.as_deref()) {
+                Outcome::Ok
+            } else {
+                Outcome::Failed
+            }
+        }
+        Command::UsbCommands { prefix } => {
+            let entries = usb::completions(prefix.as_deref().unwrap_or_default());
+            if entries.is_empty() {
+                eprintln!("No USB commands start with {}", prefix.as_deref().unwrap_or_default());
+                Outcome::Failed
+            } else {
+                println!("{}", usb::format_catalog(&entries));
+                Outcome::Ok
+            }
+        }
+        Command::PreviewFilter { log_filter, file } => match std::fs::read_to_string(file) {
+            Ok(log) => {
+                println!("{}", preview::preview(&log, log_filter, PREVIEW_SAMPLES));
+                Outcome::Ok
+            }
+            Err(e) => {
+                eprintln!("Failed to read log file {}: {}", file, e);
+                Outcome::Failed
+            }
+        },
+        command => {
+            match command {
+                Command::SetUpdateInterval { start_time, end_time, .. } => {
+                    println!("Window: {}", schedule::describe_window(*start_time, *end_time, config.timezone));
+                }
+                Command::Raw { .. } => eprintln!("Warning: raw payloads bypass local validation and are sent as written"),
+                _ => {}
+            }
+            dispatch(client, config, command).await
+        }
+    }
+}
+
+/// Parses one statement. Error spans are moved to be relative to the statement's whole line.
+fn parse_statement(statement: &Statement, ctx: &ParseContext) -> Result<Command, ParseError> {
+    parse_command_with(statement.text, ctx).map_err(|e| e.offset(statement.start))
+}
+
+/// Prints a rendered diagnostic, prefixed with `origin:line:column` when the input came from a script.
+/// `start` is the byte offset of the diagnostic within the statement's line.
+fn report(origin: Option<&str>, statement: &Statement, start: usize, rendered: &str) {
+    match origin {
+        Some(origin) => {
+            let start = start.min(statement.line.len());
+            let column = statement.line[..start].chars().count() + 1;
+            eprintln!("{}:{}:{}: {}", origin, statement.line_number, column, rendered);
         }
+        None => eprintln!("{}", rendered),
+    }
+}
+
+/// Asks before sending a USB command that is dangerous or not in the catalog. Without a
+/// terminal to ask on, only `--force` lets it through.
+fn confirm_usb_command(command: &Command, force: bool) -> Result<(), String> {
+    let Command::Command { node_id, command: usb } = command else {
+        return Ok(());
+    };
+    let Some(reason) = usb::caution(usb) else {
+        return Ok(());
+    };
+    if force {
+        eprintln!("Warning: {}; sending because of --force", reason);
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(format!("Refused: {}; use --force to send it", reason));
+    }
+
+    let target = node_id.as_ref().map_or("all nodes".to_string(), |nodes| format!("node {}", nodes));
+    print!("{}. Send it to {}? [y/N] ", reason, target);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).map_err(|e| e.to_string())?;
+    if matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        Ok(())
+    } else {
+        Err("Not sent".to_string())
+    }
+}
+
+/// Parses and validates one statement, printing any error or warning.
+fn parse_reported(origin: Option<&str>, statement: &Statement, ctx: &ParseContext, force: bool) -> Option<Command> {
+    match parse_statement(statement, ctx) {
         Ok(command) => {
-            match client.send_command(&command).await {
-                Ok(result) => {
-                    println!("{}", result);
-                    Ok(())
+            if let Some(warning) = parser::deprecation_warning(statement.text, ctx) {
+                let warning = warning.offset(statement.start);
+                report(origin, statement, warning.span.start, &warning.render(statement.line));
+            }
+            match schedule::validate(&command, Utc::now()) {
+                Ok(warnings) => {
+                    for warning in warnings {
+                        report(origin, statement, statement.start, &format!("Warning: {}", warning));
+                    }
                 }
                 Err(e) => {
-                    eprintln!("{}", e);
-                    std::process::exit(1);
+                    report(origin, statement, statement.start, &format!("Schedule error: {}", e));
+                    return None;
                 }
             }
+            if let Err(message) = confirm_usb_command(&command, force) {
+                report(origin, statement, statement.start, &message);
+                return None;
+            }
+            Some(command)
+        }
+        Err(e) => {
+            report(origin, statement, e.span().start, &e.render(statement.line));
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    sent: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+/// Runs a sequence of statements. Everything is parsed before anything is sent, so with
+/// `OnError::Stop` a syntax error anywhere means no command is sent at all.
+async fn run_statements(
+    client: &Client,
+    config: &Config,
+    statements: &[Statement<'_>],
+    origin: Option<&str>,
+    on_error: OnError,
+    ctx: &ParseContext<'_>,
+    force: bool,
+) -> Summary {
+    let mut summary = Summary::default();
+
+    let parsed: Vec<Option<Command>> = statements
+        .iter()
+        .map(|statement| parse_reported(origin, statement, ctx, force))
+        .collect();
+    summary.failed = parsed.iter().filter(|command| command.is_none()).count();
+    if summary.failed > 0 && on_error == OnError::Stop {
+        summary.skipped = parsed.iter().flatten().count();
+        return summary;
+    }
+
+    for (idx, (statement, command)) in statements.iter().zip(&parsed).enumerate() {
+        let Some(command) = command else { continue };
+        if let Some(origin) = origin {
+            println!("{}:{}: {}", origin, statement.line_number, statement.text.trim());
+        }
+
+        let stop = match execute(client, config, ctx.registry(), command).await {
+            Outcome::Ok => {
+                summary.sent += 1;
+                false
+            }
+            Outcome::Failed => {
+                summary.failed += 1;
+                on_error == OnError::Stop
+            }
+            Outcome::Unauthorized => {
+                summary.failed += 1;
+                eprintln!("Authentication failed; not sending any more commands");
+                true
+            }
+            Outcome::Quit => true,
+        };
+        if stop {
+            summary.skipped = parsed[idx + 1..].iter().flatten().count();
+            break;
+        }
+    }
+
+    summary
+}
+
+async fn execute_script(client: &Client, config: &Config, ctx: &ParseContext<'_>, path: &str, on_error: OnError, force: bool) -> Result<()> {
+    let (origin, source) = if path == "-" {
+        ("<stdin>", io::read_to_string(io::stdin()).context("Failed to read script from standard input")?)
+    } else {
+        (path, std::fs::read_to_string(path).context(format!("Failed to read script {}", path))?)
+    };
+
+    let statements = script::statements(&source);
+    let summary = run_statements(client, config, &statements, Some(origin), on_error, ctx, force).await;
+    println!(
+        "Script finished: {} sent, {} failed, {} skipped",
+        summary.sent, summary.failed, summary.skipped
+    );
+
+    if summary.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn execute_json(client: &Client, config: &Config, ctx: &ParseContext<'_>, payload: &str, force: bool) -> Result<()> {
+    // Reported like parse errors of --command rather than propagated to main
+    let command = match wire::command_from_json(payload, config.wire_dialect, ctx.registry()) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = parser::check_node_strings(&command, ctx, payload) {
+        eprintln!("{}", e.render(payload));
+        std::process::exit(1);
+    }
+    match schedule::validate(&command, Utc::now()) {
+        Ok(warnings) => {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
         }
         Err(e) => {
-            eprintln!("Parse error: {}", e);
+            eprintln!("Schedule error: {}", e);
             std::process::exit(1);
         }
     }
+    if let Err(message) = confirm_usb_command(&command, force) {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+    match execute(client, config, ctx.registry(), &command).await {
+        Outcome::Ok => Ok(()),
+        Outcome::Failed | Outcome::Unauthorized | Outcome::Quit => std::process::exit(1),
+    }
 }
 
-async fn interactive_mode(client: &Client) -> Result<()> {
+async fn execute_raw_json(client: &Client, config: &Config, ctx: &ParseContext<'_>, payload: &str) -> Result<()> {
+    let command = match wire::raw_from_json(payload) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    match execute(client, config, ctx.registry(), &command).await {
+        Outcome::Ok => Ok(()),
+        Outcome::Failed | Outcome::Unauthorized | Outcome::Quit => std::process::exit(1),
+    }
+}
+
+async fn execute_single_command(client: &Client, config: &Config, ctx: &ParseContext<'_>, command_str: &str, on_error: OnError, force: bool) -> Result<()> {
+    let statements = script::split_line(command_str, 1);
+    if statements
+        .iter()
+        .any(|statement| matches!(parse_statement(statement, ctx), Ok(Command::Quit)))
+    {
+        eprintln!("Quit command is only valid in interactive mode");
+        std::process::exit(1);
+    }
+
+    let summary = run_statements(client, config, &statements, None, on_error, ctx, force).await;
+    if summary.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn interactive_mode(client: &Client, config: &Config, ctx: &ParseContext<'_>, on_error: OnError, force: bool) -> Result<()> {
     println!("MoonBlokz Telemetry CLI - Interactive Mode");
     println!("Type 'quit', 'exit', or 'bye' to exit");
     println!();
     
-    loop {
-        // Print prompt
-        print!("> ");
-        io::stdout().flush()?;
-        
-        // Read input
+    // Line editing, history and Tab completion when a person is typing
+    let mut editor = if io::stdin().is_terminal() {
+        let editor_config = rustyline::Config::builder().completion_type(rustyline::CompletionType::List).auto_add_history(true).build();
+        let mut editor = rustyline::Editor::<completion::LineHelper, rustyline::history::DefaultHistory>::with_config(editor_config)?;
+        editor.set_helper(Some(completion::LineHelper { registry: ctx.registry() }));
+        Some(editor)
+    } else {
+        None
+    };
+    
+    'repl: loop {
         let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            eprintln!("\nError reading input");
-            continue;
+        if let Some(editor) = editor.as_mut() {
+            match editor.readline("> ") {
+                Ok(line) => input = line,
+                // Ctrl-D or Ctrl-C
+                Err(rustyline::error::ReadlineError::Eof | rustyline::error::ReadlineError::Interrupted) => break,
+                Err(_) => {
+                    eprintln!("Error reading input");
+                    continue;
+                }
+            }
+        } else {
+            // Print prompt
+            print!("> ");
+            io::stdout().flush()?;
+            
+            // Read input
+            match io::stdin().read_line(&mut input) {
+                // End of input, e.g. the end of piped commands
+                Ok(0) => {
+                    println!();
+                    break;
+                }
+                Ok(_) => {}
+                Err(_) => {
+                    eprintln!("\nError reading input");
+                    continue;
+                }
+            }
         }
         
         let input = input.trim();
         
-        // Skip empty lines
-        if input.is_empty() {
-            continue;
+        // Parse every command on the line before sending any of them
+        let mut commands = Vec::new();
+        for statement in script::split_line(input, 1) {
+            match parse_reported(None, &statement, ctx, force) {
+                Some(command) => commands.push(command),
+                None => continue 'repl,
+            }
         }
         
-        // Parse command
-        match parse_command(input) {
-            Ok(Command::Quit) => {
-                println!("Goodbye!");
-                break;
-            }
-            Ok(command) => {
-                // Send command
-                match client.send_command(&command).await {
-                    Ok(result) => {
-                        println!("{}", result);
-                    }
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        // Check if it's an authentication error - if so, exit
-                        if e.to_string().contains("401 Unauthorized") {
-                            eprintln!("Authentication failed. Please check your API key in the config file.");
-                            std::process::exit(1);
-                        }
-                    }
+        for (idx, command) in commands.iter().enumerate() {
+            match execute(client, config, ctx.registry(), command).await {
+                Outcome::Quit => {
+                    println!("Goodbye!");
+                    break 'repl;
                 }
-            }
-            Err(e) => {
-                eprintln!("Parse error: {}", e);
+                // Every further command would be rejected too
+                Outcome::Unauthorized => {
+                    eprintln!("Authentication failed; exiting");
+                    std::process::exit(1);
+                }
+                // Like a script, the rest of the line is skipped unless --on-error=continue
+                Outcome::Failed if on_error == OnError::Stop && idx + 1 < commands.len() => {
+                    eprintln!("Skipped the remaining {} command(s) on this line", commands.len() - idx - 1);
+                    break;
+                }
+                Outcome::Ok | Outcome::Failed => {}
             }
         }
     }
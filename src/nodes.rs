@@ -0,0 +1,378 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::config::NodeInfo;
+use crate::parser::{closest, ParseError, Span};
+
+/// Largest number of nodes a single `a-b` range may cover, to catch typos like `21-2100`.
+const MAX_RANGE_LEN: u32 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeItem {
+    /// `*` - every known node
+    All,
+    /// `21` or `30-35`
+    Include(RangeInclusive<u32>),
+    /// `!24` or `!30-35`
+    Exclude(RangeInclusive<u32>),
+}
+
+/// The value of a `node_id` parameter: one node or a set of nodes.
+///
+/// A selector that consists only of exclusions (`!24`) starts from every known node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeSelector {
+    items: Vec<NodeItem>,
+}
+
+impl From<u32> for NodeSelector {
+    fn from(id: u32) -> Self {
+        Self {
+            items: vec![NodeItem::Include(id..=id)],
+        }
+    }
+}
+
+/// Writes the selector in `node_id` syntax. Names and tags have already been resolved, so they appear as IDs.
+impl fmt::Display for NodeSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, item) in self.items.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            let range = match item {
+                NodeItem::All => {
+                    f.write_str("*")?;
+                    continue;
+                }
+                NodeItem::Include(range) => range,
+                NodeItem::Exclude(range) => {
+                    f.write_str("!")?;
+                    range
+                }
+            };
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}-{}", range.start(), range.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl NodeSelector {
+    /// Parses a comma-separated list of node items. `span` locates `text` in the input line.
+    ///
+    /// Besides IDs and ranges, items may be inventory names (`roof-east`) or tags (`@rooftop`),
+    /// which are resolved to node IDs here.
+    pub fn parse(text: &str, span: &Span, inventory: &[NodeInfo]) -> Result<Self, ParseError> {
+        let mut items = Vec::new();
+        let mut pos = 0;
+
+        for raw in text.split(',') {
+            let lead = raw.len() - raw.trim_start().len();
+            let item = raw.trim();
+            let item_span = span.start + pos + lead..span.start + pos + lead + item.len();
+            pos += raw.len() + 1;
+
+            let invalid = |reason| ParseError::InvalidValue {
+                name: "node_id",
+                reason,
+                span: item_span.clone(),
+            };
+
+            if item == "*" {
+                items.push(NodeItem::All);
+                continue;
+            }
+
+            let (exclude, target) = match item.strip_prefix('!') {
+                Some(rest) => (true, rest.trim_start()),
+                None => (false, item),
+            };
+            let wrap = |range| {
+                if exclude {
+                    NodeItem::Exclude(range)
+                } else {
+                    NodeItem::Include(range)
+                }
+            };
+
+            if let Some(tag) = target.strip_prefix('@') {
+                let tagged: Vec<u32> = inventory
+                    .iter()
+                    .filter(|node| node.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+                    .map(|node| node.id)
+                    .collect();
+                if tagged.is_empty() {
+                    let tags: Vec<&str> = inventory.iter().flat_map(|n| n.tags.iter().map(String::as_str)).collect();
+                    return Err(ParseError::UnknownNode {
+                        name: target.to_string(),
+                        suggestion: closest(tag, tags).map(|t| format!("@{}", t)),
+                        span: item_span,
+                    });
+                }
+                items.extend(tagged.into_iter().map(|id| wrap(id..=id)));
+                continue;
+            }
+
+            if !target.starts_with(|c: char| c.is_ascii_digit()) {
+                let node = inventory
+                    .iter()
+                    .find(|node| node.name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(target)));
+                match node {
+                    Some(node) => items.push(wrap(node.id..=node.id)),
+                    None if target.is_empty() => {
+                        return Err(invalid("must be a positive integer, a range like 30-35, *, !<id>, a node name or @tag"))
+                    }
+                    None => {
+                        let names = inventory.iter().filter_map(|n| n.name.as_deref());
+                        return Err(ParseError::UnknownNode {
+                            name: target.to_string(),
+                            suggestion: closest(target, names).map(str::to_string),
+                            span: item_span,
+                        });
+                    }
+                }
+                continue;
+            }
+
+            let range = match target.split_once('-') {
+                Some((start, end)) => {
+                    let start = parse_id(start).ok_or_else(|| invalid("range bounds must be positive integers"))?;
+                    let end = parse_id(end).ok_or_else(|| invalid("range bounds must be positive integers"))?;
+                    if start > end {
+                        return Err(invalid("range start must not be greater than its end"));
+                    }
+                    if end - start >= MAX_RANGE_LEN {
+                        return Err(invalid("range covers more than 1024 nodes"));
+                    }
+                    start..=end
+                }
+                None => parse_id(target).map(|id| id..=id).ok_or_else(|| invalid("must be a positive integer"))?,
+            };
+            items.push(wrap(range));
+        }
+
+        Ok(Self { items })
+    }
+
+    /// Returns the node ID if this selector names exactly one node.
+    pub fn single(&self) -> Option<u32> {
+        match self.items.as_slice() {
+            [NodeItem::Include(range)] if range.start() == range.end() => Some(*range.start()),
+            _ => None,
+        }
+    }
+
+    /// Expands the selector into a sorted list of node IDs.
+    ///
+    /// `known` is the station's node list; it is only needed for `*` and exclusions.
+    pub fn resolve(&self, known: &[u32]) -> Result<Vec<u32>> {
+        let needs_known = self
+            .items
+            .iter()
+            .all(|item| !matches!(item, NodeItem::Include(_)))
+            || self.items.contains(&NodeItem::All);
+        if needs_known && known.is_empty() {
+            return Err(anyhow!(
+                "node_id uses '*' or '!' but no nodes are known; add known-nodes or [[nodes]] to the config file"
+            ));
+        }
+
+        let mut ids = BTreeSet::new();
+        if self.items.iter().all(|item| matches!(item, NodeItem::Exclude(_))) {
+            ids.extend(known.iter().copied());
+        }
+        for item in &self.items {
+            match item {
+                NodeItem::All => ids.extend(known.iter().copied()),
+                NodeItem::Include(range) => ids.extend(range.clone()),
+                NodeItem::Exclude(_) => {}
+            }
+        }
+        for item in &self.items {
+            if let NodeItem::Exclude(range) = item {
+                ids.retain(|id| !range.contains(id));
+            }
+        }
+
+        if ids.is_empty() {
+            return Err(anyhow!("node_id selects no nodes"));
+        }
+        Ok(ids.into_iter().collect())
+    }
+}
+
+/// Finds an inventory entry by name (case-insensitive) or by ID.
+pub fn find_node<'a>(inventory: &'a [NodeInfo], name: &str) -> Option<&'a NodeInfo> {
+    inventory.iter().find(|node| {
+        node.name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(name)) || name.parse() == Ok(node.id)
+    })
+}
+
+/// Formats the inventory as a table, one node per line.
+pub fn format_inventory(inventory: &[NodeInfo]) -> String {
+    let rows: Vec<[String; 5]> = inventory
+        .iter()
+        .map(|node| {
+            [
+                node.id.to_string(),
+                node.name.clone().unwrap_or_default(),
+                node.location.clone().unwrap_or_default(),
+                node.tags.iter().map(|t| format!("@{}", t)).collect::<Vec<_>>().join(" "),
+                node.probe_hostname.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let header = ["ID", "NAME", "LOCATION", "TAGS", "PROBE"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: &[&str]| {
+        let padded: Vec<String> = cells.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let mut out = line(&header);
+    for row in &rows {
+        out.push('\n');
+        out.push_str(&line(&row.each_ref().map(String::as_str)));
+    }
+    out
+}
+
+/// Formats a single inventory entry, one field per line.
+pub fn format_node(node: &NodeInfo) -> String {
+    let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    let tags = if node.tags.is_empty() {
+        "-".to_string()
+    } else {
+        node.tags.join(", ")
+    };
+    format!(
+        "id:             {}\nname:           {}\nlocation:       {}\ntags:           {}\nprobe hostname: {}",
+        node.id,
+        or_dash(&node.name),
+        or_dash(&node.location),
+        tags,
+        or_dash(&node.probe_hostname)
+    )
+}
+
+fn parse_id(s: &str) -> Option<u32> {
+    s.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> NodeSelector {
+        NodeSelector::parse(text, &(0..text.len()), &inventory()).unwrap()
+    }
+
+    fn node(id: u32, name: &str, tags: &[&str]) -> NodeInfo {
+        NodeInfo {
+            id,
+            name: Some(name.to_string()),
+            location: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            probe_hostname: None,
+        }
+    }
+
+    fn inventory() -> Vec<NodeInfo> {
+        vec![
+            node(21, "roof-east", &["rooftop", "outdoor"]),
+            node(22, "roof-west", &["rooftop", "outdoor"]),
+            node(30, "lab-bench", &["indoor"]),
+        ]
+    }
+
+    #[test]
+    fn test_single_node() {
+        assert_eq!(parse("21").single(), Some(21));
+        assert_eq!(parse("21-21").single(), Some(21));
+        assert_eq!(parse("21,22").single(), None);
+    }
+
+    #[test]
+    fn test_resolve_lists_and_ranges() {
+        assert_eq!(parse("21,22,30-33,22").resolve(&[]).unwrap(), vec![21, 22, 30, 31, 32, 33]);
+        assert_eq!(parse("30-35,!32-33").resolve(&[]).unwrap(), vec![30, 31, 34, 35]);
+    }
+
+    #[test]
+    fn test_resolve_wildcards_against_known_nodes() {
+        let known = [21, 22, 24, 30];
+        assert_eq!(parse("*").resolve(&known).unwrap(), vec![21, 22, 24, 30]);
+        assert_eq!(parse("!24").resolve(&known).unwrap(), vec![21, 22, 30]);
+        assert_eq!(parse("*,40,!21").resolve(&known).unwrap(), vec![22, 24, 30, 40]);
+        assert!(parse("!24").resolve(&[]).is_err());
+        assert!(parse("21,!21").resolve(&known).is_err());
+    }
+
+    #[test]
+    fn test_display_uses_node_id_syntax() {
+        assert_eq!(parse("*, !30-33,22").to_string(), "*,!30-33,22");
+        assert_eq!(parse("roof-east,@indoor").to_string(), "21,30");
+        assert_eq!(NodeSelector::from(7).to_string(), "7");
+    }
+
+    #[test]
+    fn test_invalid_items_point_at_item() {
+        let text = "21, 35-30";
+        let err = NodeSelector::parse(text, &(10..10 + text.len()), &[]).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "node_id", .. }));
+        assert_eq!(err.span(), 14..19);
+
+        for text in ["abc", "1-", "!x", "1-5000", ""] {
+            assert!(NodeSelector::parse(text, &(0..text.len()), &[]).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_names_and_tags_resolve_through_inventory() {
+        assert_eq!(parse("ROOF-EAST").single(), Some(21));
+        assert_eq!(parse("@rooftop").resolve(&[]).unwrap(), vec![21, 22]);
+        assert_eq!(parse("@outdoor,lab-bench,!roof-west").resolve(&[]).unwrap(), vec![21, 30]);
+        assert_eq!(parse("!@rooftop").resolve(&[21, 22, 30]).unwrap(), vec![30]);
+    }
+
+    #[test]
+    fn test_unknown_names_suggest_inventory_entries() {
+        let err = NodeSelector::parse("roof-est", &(0..8), &inventory()).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownNode { suggestion: Some(ref s), .. } if s == "roof-east"));
+
+        let err = NodeSelector::parse("@rooftops", &(0..9), &inventory()).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownNode { suggestion: Some(ref s), .. } if s == "@rooftop"));
+
+        let err = NodeSelector::parse("kitchen", &(0..7), &inventory()).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownNode { suggestion: None, .. }));
+    }
+
+    #[test]
+    fn test_find_and_format_nodes() {
+        let inventory = inventory();
+        assert_eq!(find_node(&inventory, "Lab-Bench").map(|n| n.id), Some(30));
+        assert_eq!(find_node(&inventory, "22").map(|n| n.id), Some(22));
+        assert!(find_node(&inventory, "23").is_none());
+
+        let table = format_inventory(&inventory);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "ID  NAME       LOCATION  TAGS               PROBE");
+        assert_eq!(lines[1], "21  roof-east            @rooftop @outdoor");
+
+        assert!(format_node(&inventory[2]).contains("tags:           indoor"));
+    }
+}
@@ -1,396 +1,1246 @@
-use anyhow::{anyhow, Result};
-use chrono::{DateTime, Utc};
-use serde_json::{json, Value};
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::fmt;
+use std::ops::Range;
+use std::vec::IntoIter;
+use thiserror::Error;
+
+use crate::config::NodeInfo;
+use crate::lexer::{quote, tokenize, Token, TokenKind};
+use crate::nodes::NodeSelector;
+use crate::time::{self, TimestampError};
+use crate::registry::{self, CommandSpec, ParamSpec, ParamType, Registry, Target};
+use crate::wire::{self, WireCommand};
+
+/// Byte range into the input line passed to `parse_command`.
+pub type Span = Range<usize>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("Missing closing parenthesis")]
+    MissingClosingParen { span: Span },
+    #[error("Unknown command: {name}{}", did_you_mean(.suggestion))]
+    UnknownCommand { name: String, suggestion: Option<&'static str>, span: Span },
+    #[error("{command} requires parameters")]
+    MissingArguments { command: &'static str, span: Span },
+    #[error("Missing {name} parameter")]
+    MissingParameter { name: &'static str, span: Span },
+    #[error("Unknown parameter {name} for {command}{}", did_you_mean(.suggestion))]
+    UnknownParameter { command: &'static str, name: String, suggestion: Option<&'static str>, span: Span },
+    #[error("Unknown node {name}{}", did_you_mean(&.suggestion.as_deref()))]
+    UnknownNode { name: String, suggestion: Option<String>, span: Span },
+    #[error("Duplicate parameter {name}")]
+    DuplicateParameter { name: String, span: Span },
+    #[error("{name} is required for {command} command")]
+    RequiredParameter { command: &'static str, name: &'static str, span: Span },
+    #[error("Invalid {name}: {reason}")]
+    InvalidValue { name: &'static str, reason: &'static str, span: Span },
+    #[error("Invalid ISO 8601 timestamp {value}: expected {expected}")]
+    InvalidTimestamp { value: String, expected: &'static str, span: Span },
+    #[error("Unknown time zone {name}{}", did_you_mean(.suggestion))]
+    UnknownTimeZone { name: String, suggestion: Option<&'static str>, span: Span },
+    #[error("Unterminated string")]
+    UnterminatedString { span: Span },
+    #[error("Invalid escape sequence (expected \\\", \\\\, \\n, \\t or \\xNN with NN at most 7F)")]
+    InvalidEscape { span: Span },
+    #[error("Parameter {key} has no value (expected {key}=<value>)")]
+    DanglingKey { key: String, span: Span },
+    #[error("Missing value for {key}")]
+    MissingValue { key: String, span: Span },
+    #[error("Invalid raw payload: {reason}")]
+    InvalidPayload { reason: String, span: Span },
+    #[error("Positional arguments must come before named ones")]
+    PositionalAfterNamed { span: Span },
+    #[error("{command} takes {}", positional_count(*.max))]
+    TooManyArguments { command: &'static str, max: usize, span: Span },
+    #[error("{name} is given both by position and by name")]
+    ConflictingArgument { name: &'static str, span: Span },
+    #[error("Expected {expected}, found {found}")]
+    UnexpectedToken { expected: &'static str, found: String, span: Span },
+    #[error("The registry entry of {command} does not match the built-in command")]
+    InvalidDefinition { command: &'static str, span: Span },
+}
 
-#[derive(Debug, Clone)]
+impl ParseError {
+    /// The part of the input the error refers to.
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::MissingClosingParen { span }
+            | ParseError::UnknownCommand { span, .. }
+            | ParseError::MissingArguments { span, .. }
+            | ParseError::MissingParameter { span, .. }
+            | ParseError::UnknownParameter { span, .. }
+            | ParseError::UnknownNode { span, .. }
+            | ParseError::DuplicateParameter { span, .. }
+            | ParseError::RequiredParameter { span, .. }
+            | ParseError::InvalidValue { span, .. }
+            | ParseError::InvalidTimestamp { span, .. }
+            | ParseError::UnknownTimeZone { span, .. }
+            | ParseError::UnterminatedString { span }
+            | ParseError::InvalidEscape { span }
+            | ParseError::DanglingKey { span, .. }
+            | ParseError::MissingValue { span, .. }
+            | ParseError::InvalidPayload { span, .. }
+            | ParseError::PositionalAfterNamed { span }
+            | ParseError::TooManyArguments { span, .. }
+            | ParseError::ConflictingArgument { span, .. }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::InvalidDefinition { span, .. } => span.clone(),
+        }
+    }
+
+    /// Moves the span `by` bytes to the right, for input that was parsed out of a longer line.
+    pub fn offset(mut self, by: usize) -> Self {
+        match &mut self {
+            ParseError::MissingClosingParen { span }
+            | ParseError::UnknownCommand { span, .. }
+            | ParseError::MissingArguments { span, .. }
+            | ParseError::MissingParameter { span, .. }
+            | ParseError::UnknownParameter { span, .. }
+            | ParseError::UnknownNode { span, .. }
+            | ParseError::DuplicateParameter { span, .. }
+            | ParseError::RequiredParameter { span, .. }
+            | ParseError::InvalidValue { span, .. }
+            | ParseError::InvalidTimestamp { span, .. }
+            | ParseError::UnknownTimeZone { span, .. }
+            | ParseError::UnterminatedString { span }
+            | ParseError::InvalidEscape { span }
+            | ParseError::DanglingKey { span, .. }
+            | ParseError::MissingValue { span, .. }
+            | ParseError::InvalidPayload { span, .. }
+            | ParseError::PositionalAfterNamed { span }
+            | ParseError::TooManyArguments { span, .. }
+            | ParseError::ConflictingArgument { span, .. }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::InvalidDefinition { span, .. } => *span = span.start + by..span.end + by,
+        }
+        self
+    }
+
+    /// Renders the error message followed by `source` with the offending span underlined.
+    ///
+    /// `source` must be the exact string that was parsed, or the line it was taken from
+    /// if the error has been moved with `offset`.
+    pub fn render(&self, source: &str) -> String {
+        format!("Parse error: {}\n{}", self, underline(source, &self.span()))
+    }
+}
+
+/// A remark about input that parsed successfully, such as a deprecated command name.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{alias} is deprecated, use {replacement} instead")]
+pub struct ParseWarning {
+    pub alias: &'static str,
+    pub replacement: &'static str,
+    pub span: Span,
+}
+
+impl ParseWarning {
+    /// Moves the span `by` bytes to the right, like `ParseError::offset`.
+    pub fn offset(mut self, by: usize) -> Self {
+        self.span = self.span.start + by..self.span.end + by;
+        self
+    }
+
+    /// Renders the warning followed by `source` with the deprecated name underlined.
+    pub fn render(&self, source: &str) -> String {
+        format!("Warning: {}\n{}", self, underline(source, &self.span))
+    }
+}
+
+/// Prints `source` on one line and carets under `span` on the next.
+fn underline(source: &str, span: &Span) -> String {
+    // Widened to whole characters, so a span that ends up inside one cannot break the slicing
+    let start = floor_boundary(source, span.start);
+    let end = ceil_boundary(source, span.end.clamp(start, source.len()));
+    let column = source[..start].chars().count();
+    let width = source[start..end].chars().count().max(1);
+
+    format!("  | {}\n  | {}{}", source, " ".repeat(column), "^".repeat(width))
+}
+
+/// The char boundary at or before `idx`, which may be past the end of `text`.
+fn floor_boundary(text: &str, idx: usize) -> usize {
+    let mut idx = idx.min(text.len());
+    while !text.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+/// The char boundary at or after `idx`, which must be at most `text.len()`.
+fn ceil_boundary(text: &str, idx: usize) -> usize {
+    let mut idx = idx;
+    while !text.is_char_boundary(idx) {
+        idx += 1;
+    }
+    idx
+}
+
+fn did_you_mean(suggestion: &Option<&str>) -> String {
+    suggestion.map(|s| format!(" (did you mean {}?)", s)).unwrap_or_default()
+}
+
+fn positional_count(max: usize) -> String {
+    match max {
+        0 => "no positional arguments".to_string(),
+        1 => "at most 1 positional argument".to_string(),
+        _ => format!("at most {} positional arguments", max),
+    }
+}
+
+/// Returns a warning if `input` starts with a deprecated command alias.
+pub fn deprecation_warning(input: &str, ctx: &ParseContext) -> Option<ParseWarning> {
+    let token = tokenize(input).ok()?.into_iter().next()?;
+    let TokenKind::Word(name) = token.kind else {
+        return None;
+    };
+    ctx.registry().deprecated_alias(&name).map(|(alias, replacement)| ParseWarning {
+        alias,
+        replacement,
+        span: token.span,
+    })
+}
+
+/// Returns the candidate closest to `input` by edit distance if it is near enough to be a likely typo.
+pub(crate) fn closest<'a>(input: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let input = input.to_lowercase();
+    let max_distance = (input.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|c| (strsim::damerau_levenshtein(&input, &c.to_lowercase()), c))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     SetUpdateInterval {
+        node_id: Option<NodeSelector>,
         start_time: DateTime<Utc>,
         end_time: DateTime<Utc>,
         active_period: u64,
         inactive_period: u64,
     },
     SetLogLevel {
-        node_id: Option<u32>,
+        node_id: Option<NodeSelector>,
         log_level: String,
     },
     SetLogFilter {
-        node_id: Option<u32>,
+        node_id: Option<NodeSelector>,
         log_filter: String,
     },
     Command {
-        node_id: Option<u32>,
+        node_id: Option<NodeSelector>,
         command: String,
     },
     UpdateNode {
-        node_id: Option<u32>,
+        node_id: Option<NodeSelector>,
     },
     UpdateProbe {
-        node_id: Option<u32>,
+        node_id: Option<NodeSelector>,
     },
     RebootProbe {
-        node_id: Option<u32>,
+        node_id: Option<NodeSelector>,
     },
     StartMeasurement {
-        node_id: u32,
+        node_id: NodeSelector,
         sequence: u32,
     },
+    /// Shows what `log_filter` would keep of a collector log file, without sending anything
+    PreviewFilter {
+        log_filter: String,
+        file: String,
+    },
+    /// `usb_commands` lists the USB command catalog, `usb_commands <prefix>` the entries starting with it
+    UsbCommands {
+        prefix: Option<String>,
+    },
+    /// `nodes` lists the inventory, `nodes show <name>` shows one entry
+    Nodes {
+        name: Option<String>,
+    },
+    /// `help` lists the commands, `help <command>` describes one
+    Help {
+        name: Option<String>,
+    },
+    /// A command from the `commands-file`, sent to the hub as written
+    Custom {
+        name: &'static str,
+        node_id: Option<NodeSelector>,
+        params: Vec<(&'static str, ArgValue)>,
+    },
+    /// `raw(<json>)`: a request body sent to the hub as is, checked only for its shape
+    Raw {
+        payload: Value,
+    },
     Quit,
 }
 
+/// A parameter value of a registry command, typed by its `ParamType`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgValue {
+    Integer(u64),
+    Text(String),
+    Timestamp(DateTime<Utc>),
+    /// Seconds
+    Duration(u64),
+}
+
+impl fmt::Display for ArgValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgValue::Integer(value) | ArgValue::Duration(value) => write!(f, "{}", value),
+            ArgValue::Text(text) => f.write_str(&quote(text)),
+            ArgValue::Timestamp(time) => f.write_str(&time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        }
+    }
+}
+
 impl Command {
+    /// The JSON request body for the hub. Multi-node commands must be expanded first.
     pub fn to_json(&self) -> Result<Value> {
         match self {
+            Command::Custom { name, node_id, params } => return wire::custom_to_json(name, node_id.as_ref(), params),
+            Command::Raw { payload } => return Ok(payload.clone()),
+            _ => {}
+        }
+        Ok(serde_json::to_value(WireCommand::try_from(self)?)?)
+    }
+
+    /// Returns the node selection, if the command names one.
+    pub fn nodes(&self) -> Option<&NodeSelector> {
+        match self {
+            Command::SetUpdateInterval { node_id, .. }
+            | Command::SetLogLevel { node_id, .. }
+            | Command::SetLogFilter { node_id, .. }
+            | Command::Command { node_id, .. }
+            | Command::UpdateNode { node_id }
+            | Command::UpdateProbe { node_id }
+            | Command::RebootProbe { node_id }
+            | Command::Custom { node_id, .. } => node_id.as_ref(),
+            Command::StartMeasurement { node_id, .. } => Some(node_id),
+            Command::PreviewFilter { .. } | Command::UsbCommands { .. } | Command::Nodes { .. } | Command::Help { .. } | Command::Raw { .. } | Command::Quit => None,
+        }
+    }
+
+    /// Whether sending the command twice has the same effect as sending it once, so that a
+    /// request the hub may already have acted on can be retried.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self, Command::SetUpdateInterval { .. } | Command::SetLogLevel { .. } | Command::SetLogFilter { .. })
+    }
+
+    /// Splits a command that targets several nodes into one command per node.
+    ///
+    /// Commands without a node selection, or with a single node, are returned unchanged.
+    /// `known` is the station's node list used to expand `*` and `!<id>`.
+    pub fn expand(&self, known: &[u32]) -> Result<Vec<Command>> {
+        let Some(nodes) = self.nodes().filter(|nodes| nodes.single().is_none()) else {
+            return Ok(vec![self.clone()]);
+        };
+
+        let ids = nodes.resolve(known)?;
+        Ok(ids
+            .into_iter()
+            .map(|id| {
+                let mut command = self.clone();
+                match &mut command {
+                    Command::SetUpdateInterval { node_id, .. }
+                    | Command::SetLogLevel { node_id, .. }
+                    | Command::SetLogFilter { node_id, .. }
+                    | Command::Command { node_id, .. }
+                    | Command::UpdateNode { node_id }
+                    | Command::UpdateProbe { node_id }
+                    | Command::RebootProbe { node_id }
+                    | Command::Custom { node_id, .. } => *node_id = Some(id.into()),
+                    Command::StartMeasurement { node_id, .. } => *node_id = id.into(),
+                    Command::PreviewFilter { .. } | Command::UsbCommands { .. } | Command::Nodes { .. } | Command::Help { .. } | Command::Raw { .. } | Command::Quit => {}
+                }
+                command
+            })
+            .collect())
+    }
+}
+
+/// Writes the command in canonical form: lower-case names, `node_id` first, timestamps in UTC
+/// and strings quoted. Parsing the output yields an equal command.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = |node_id: &Option<NodeSelector>| -> Vec<(&str, String)> {
+            node_id.iter().map(|nodes| ("node_id", nodes.to_string())).collect()
+        };
+        let timestamp = |time: &DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+
+        let (name, params) = match self {
             Command::SetUpdateInterval {
+                node_id,
                 start_time,
                 end_time,
                 active_period,
                 inactive_period,
-            } => {
-                let params = json!({
-                    "start_time": start_time.to_rfc3339(),
-                    "end_time": end_time.to_rfc3339(),
-                    "active_period": active_period,
-                    "inactive_period": inactive_period,
-                });
-
-                Ok(json!({
-                    "command": "set_update_interval",
-                    "parameters": params,
-                }))
-            }
+            } => (
+                "set_update_interval",
+                [
+                    node(node_id),
+                    vec![
+                        ("start_time", timestamp(start_time)),
+                        ("end_time", timestamp(end_time)),
+                        ("active_period", active_period.to_string()),
+                        ("inactive_period", inactive_period.to_string()),
+                    ],
+                ]
+                .concat(),
+            ),
             Command::SetLogLevel { node_id, log_level } => {
-                let mut params = json!({
-                    "log_level": log_level,
-                });
-
-                if let Some(id) = node_id {
-                    params["node id"] = json!(id);
-                }
-
-                Ok(json!({
-                    "command": "set_log_level",
-                    "parameters": params,
-                }))
+                ("set_log_level", [node(node_id), vec![("log_level", log_level.clone())]].concat())
             }
             Command::SetLogFilter { node_id, log_filter } => {
-                let mut params = json!({
-                    "log_filter": log_filter,
-                });
-
-                if let Some(id) = node_id {
-                    params["node id"] = json!(id);
-                }
-
-                Ok(json!({
-                    "command": "set_log_filter",
-                    "parameters": params,
-                }))
+                ("set_log_filter", [node(node_id), vec![("log_filter", quote(log_filter))]].concat())
             }
             Command::Command { node_id, command } => {
-                let mut params = json!({
-                    "command": command,
-                });
-
-                if let Some(id) = node_id {
-                    params["node id"] = json!(id);
-                }
-
-                Ok(json!({
-                    "command": "run_command",
-                    "parameters": params,
-                }))
+                ("run_command", [node(node_id), vec![("command", quote(command))]].concat())
             }
-            Command::UpdateNode { node_id } => {
-                let mut params = json!({});
-
-                if let Some(id) = node_id {
-                    params["node id"] = json!(id);
-                }
-
-                Ok(json!({
-                    "command": "update_node",
-                    "parameters": params,
-                }))
+            Command::UpdateNode { node_id } => ("update_node", node(node_id)),
+            Command::UpdateProbe { node_id } => ("update_probe", node(node_id)),
+            Command::RebootProbe { node_id } => ("reboot_probe", node(node_id)),
+            Command::StartMeasurement { node_id, sequence } => (
+                "start_measurement",
+                vec![("node_id", node_id.to_string()), ("sequence", sequence.to_string())],
+            ),
+            Command::PreviewFilter { log_filter, file } => {
+                ("preview_filter", vec![("log_filter", quote(log_filter)), ("file", quote(file))])
             }
-            Command::UpdateProbe { node_id } => {
-                let mut params = json!({});
-
-                if let Some(id) = node_id {
-                    params["node id"] = json!(id);
-                }
-
-                Ok(json!({
-                    "command": "update_probe",
-                    "parameters": params,
-                }))
-            }
-            Command::RebootProbe { node_id } => {
-                let mut params = json!({});
+            Command::UsbCommands { prefix: None } => return f.write_str("usb_commands"),
+            Command::UsbCommands { prefix: Some(prefix) } => return write!(f, "usb_commands {}", prefix),
+            Command::Nodes { name: None } => return f.write_str("nodes"),
+            Command::Nodes { name: Some(name) } => return write!(f, "nodes show {}", name),
+            Command::Help { name: None } => return f.write_str("help"),
+            Command::Help { name: Some(name) } => return write!(f, "help {}", name),
+            Command::Raw { payload } => return write!(f, "raw({})", payload),
+            Command::Custom { name, node_id, params } => (
+                *name,
+                [node(node_id), params.iter().map(|(key, value)| (*key, value.to_string())).collect()].concat(),
+            ),
+            Command::Quit => return f.write_str("quit"),
+        };
+
+        let params: Vec<String> = params.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        write!(f, "{}({})", name, params.join(", "))
+    }
+}
 
-                if let Some(id) = node_id {
-                    params["node id"] = json!(id);
-                }
+/// Station-specific information used while parsing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParseContext<'a> {
+    /// Node inventory used to resolve node names and `@tag` selections
+    pub inventory: &'a [NodeInfo],
+    /// Reference time for `now`, `today` and `tomorrow`; the system clock if `None`
+    pub now: Option<DateTime<Utc>>,
+    /// Zone for timestamps without a UTC offset and for `today`/`tomorrow`.
+    /// Without it, such timestamps are rejected and `today`/`tomorrow` use the system zone.
+    pub timezone: Option<Tz>,
+    /// Accept control characters in `run_command` and `log_filter` values (`--allow-control-chars`)
+    pub allow_control_chars: bool,
+    /// Known commands; the built-in ones if `None`
+    pub registry: Option<&'static Registry>,
+}
 
-                Ok(json!({
-                    "command": "reboot_probe",
-                    "parameters": params,
-                }))
-            }
-            Command::StartMeasurement { node_id, sequence } => {
-                let params = json!({
-                    "node id": node_id,
-                    "sequence": sequence,
-                });
+impl ParseContext<'_> {
+    pub fn registry(&self) -> &'static Registry {
+        self.registry.unwrap_or_else(Registry::builtin)
+    }
 
-                Ok(json!({
-                    "command": "start_measurement",
-                    "parameters": params,
-                }))
-            }
-            Command::Quit => Err(anyhow!("Quit command cannot be converted to JSON")),
-        }
+    /// The reference time, truncated to whole seconds.
+    fn now(&self) -> DateTime<Utc> {
+        self.now.unwrap_or_else(Utc::now).trunc_subsecs(0)
     }
 }
 
-pub fn parse_command(input: &str) -> Result<Command> {
-    let input = input.trim();
-
+pub fn parse_command_with(input: &str, ctx: &ParseContext) -> Result<Command, ParseError> {
     // Check for quit commands
-    let lower = input.to_lowercase();
+    let lower = input.trim().to_lowercase();
     if lower == "quit" || lower == "exit" || lower == "bye" {
         return Ok(Command::Quit);
     }
 
-    // Find command name and parameters
-    let (cmd_name, params_str) = if let Some(paren_idx) = input.find('(') {
-        if !input.ends_with(')') {
-            return Err(anyhow!("Missing closing parenthesis"));
-        }
-        let cmd = input[..paren_idx].trim();
-        let params = &input[paren_idx + 1..input.len() - 1];
-        (cmd, Some(params))
-    } else {
-        (input, None)
-    };
+    if lower.trim_start().strip_prefix("raw").is_some_and(|rest| rest.is_empty() || rest.starts_with(|c: char| c == '(' || c.is_whitespace())) {
+        return parse_raw(input);
+    }
 
-    let cmd_lower = cmd_name.to_lowercase();
+    match lower.split_whitespace().next() {
+        Some("nodes") => return parse_nodes(input),
+        Some("usb_commands") => return Ok(Command::UsbCommands { prefix: optional_word(input)? }),
+        Some("help") => return Ok(Command::Help { name: optional_word(input)? }),
+        _ => {}
+    }
 
-    match cmd_lower.as_str() {
-        "set_update_interval" => {
-            let params = params_str.ok_or_else(|| anyhow!("set_update_interval requires parameters"))?;
-            parse_set_update_interval(params)
-        }
-        "set_log_level" => {
-            let params = params_str.ok_or_else(|| anyhow!("set_log_level requires parameters"))?;
-            parse_set_log_level(params)
-        }
-        "set_log_filter" => {
-            let params = params_str.ok_or_else(|| anyhow!("set_log_filter requires parameters"))?;
-            parse_set_log_filter(params)
-        }
-        "run_command" => {
-            let params = params_str.ok_or_else(|| anyhow!("command requires parameters"))?;
-            parse_command_cmd(params)
-        }
-        "update_node" => {
-            let params = params_str.unwrap_or("");
-            parse_update_node(params)
-        }
-        "update_probe" => {
-            let params = params_str.unwrap_or("");
-            parse_update_probe(params)
+    let mut tokens = tokenize(input)?.into_iter();
+
+    let (cmd_name, name_span) = match tokens.next() {
+        Some(Token {
+            kind: TokenKind::Word(name),
+            span,
+        }) => (name, span),
+        Some(token) => {
+            return Err(ParseError::UnexpectedToken {
+                expected: "command name",
+                found: token.kind.describe(),
+                span: token.span,
+            })
         }
-        "reboot_probe" => {
-            let params = params_str.unwrap_or("");
-            parse_reboot_probe(params)
+        None => {
+            return Err(ParseError::UnknownCommand {
+                name: String::new(),
+                suggestion: None,
+                span: 0..input.len(),
+            })
         }
-        "start_measurement" => {
-            let params = params_str.ok_or_else(|| anyhow!("start_measurement requires parameters"))?;
-            parse_start_measurement(params)
+    };
+
+    // Find parameters
+    let params = match tokens.next() {
+        Some(Token {
+            kind: TokenKind::LParen,
+            span,
+        }) => Some(parse_args(input, span.start, &mut tokens)?),
+        Some(token) => {
+            return Err(ParseError::UnexpectedToken {
+                expected: "'('",
+                found: token.kind.describe(),
+                span: token.span,
+            })
         }
-        _ => Err(anyhow!("Unknown command: {}", cmd_name)),
+        None => None,
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(ParseError::UnexpectedToken {
+            expected: "end of input",
+            found: token.kind.describe(),
+            span: token.span,
+        });
     }
-}
 
-fn parse_params(params_str: &str) -> Result<Vec<(String, String)>> {
-    let mut params = Vec::new();
-    let mut current_key = String::new();
-    let mut current_value = String::new();
-    let mut in_value = false;
-    let mut in_quotes = false;
-    let mut chars = params_str.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        if in_value {
-            if ch == '"' {
-                in_quotes = !in_quotes;
-                current_value.push(ch);
-            } else if ch == ',' && !in_quotes {
-                params.push((current_key.trim().to_string(), current_value.trim().to_string()));
-                current_key.clear();
-                current_value.clear();
-                in_value = false;
-            } else {
-                current_value.push(ch);
-            }
-        } else {
-            if ch == '=' {
-                in_value = true;
-            } else if !ch.is_whitespace() || !current_key.is_empty() {
-                current_key.push(ch);
-            }
+    let Some(spec) = ctx.registry().find(&cmd_name) else {
+        return Err(ParseError::UnknownCommand {
+            suggestion: closest(&cmd_name, ctx.registry().names().chain(registry::KEYWORDS.iter().copied())),
+            name: cmd_name,
+            span: name_span,
+        });
+    };
+    let mut args = match params {
+        Some(args) => args,
+        None if spec.takes_no_arguments() => Args {
+            params: Vec::new(),
+            positional: Vec::new(),
+            span: name_span.end..name_span.end,
+        },
+        None => {
+            return Err(ParseError::MissingArguments {
+                command: &spec.name,
+                span: name_span,
+            })
         }
+    };
+    args.resolve_positional(input, spec)?;
+    parse_with_spec(spec, &args, ctx)
+}
+
+/// Parses `nodes` and `nodes show <name>`.
+fn parse_nodes(input: &str) -> Result<Command, ParseError> {
+    let words: Vec<(usize, &str)> = input
+        .split_whitespace()
+        .map(|word| (word.as_ptr() as usize - input.as_ptr() as usize, word))
+        .collect();
+    let span = |&(start, word): &(usize, &str)| start..start + word.len();
+
+    match words.as_slice() {
+        [_] => Ok(Command::Nodes { name: None }),
+        [_, show, name] if show.1.eq_ignore_ascii_case("show") => Ok(Command::Nodes {
+            name: Some(name.1.to_string()),
+        }),
+        [_, show] if show.1.eq_ignore_ascii_case("show") => Err(ParseError::UnexpectedToken {
+            expected: "node name",
+            found: "end of input".to_string(),
+            span: input.len()..input.len(),
+        }),
+        [_, show, _, extra, ..] if show.1.eq_ignore_ascii_case("show") => Err(ParseError::UnexpectedToken {
+            expected: "end of input",
+            found: format!("'{}'", extra.1),
+            span: span(extra),
+        }),
+        [_, other, ..] => Err(ParseError::UnexpectedToken {
+            expected: "'show' or end of input",
+            found: format!("'{}'", other.1),
+            span: span(other),
+        }),
+        [] => unreachable!("caller checked for the nodes keyword"),
     }
+}
 
-    if !current_key.is_empty() || !current_value.is_empty() {
-        params.push((current_key.trim().to_string(), current_value.trim().to_string()));
+/// Parses `raw(<json>)`. The payload is checked only for its shape, see `wire::check_raw`.
+fn parse_raw(input: &str) -> Result<Command, ParseError> {
+    let start = input.len() - input.trim_start().len();
+    let end = input.trim_end().len();
+    let name = start..start + "raw".len();
+    let open = end - input[name.end..end].trim_start().len();
+    if open == end {
+        return Err(ParseError::MissingArguments { command: "raw", span: name });
+    }
+    if !input[open..].starts_with('(') {
+        let found = input[open..end].split_whitespace().next().unwrap_or_default();
+        return Err(ParseError::UnexpectedToken {
+            expected: "'('",
+            found: format!("'{}'", found),
+            span: open..open + found.len(),
+        });
+    }
+    if end == open + 1 || !input[..end].ends_with(')') {
+        return Err(ParseError::MissingClosingParen { span: open..end });
     }
 
-    Ok(params)
+    let body = open + 1..end - 1;
+    let text = &input[body.clone()];
+    let payload: Value = serde_json::from_str(text).map_err(|e| {
+        // serde_json counts lines and columns from 1 and drops the position into the message
+        let line_start: usize = text.split_inclusive('\n').take(e.line().saturating_sub(1)).map(str::len).sum();
+        // The column counts bytes, so it can point inside a character; underline all of it
+        let at = floor_boundary(input, (body.start + line_start + e.column().saturating_sub(1)).min(body.end));
+        let width = input[at..body.end].chars().next().map_or(0, char::len_utf8);
+        let message = e.to_string();
+        ParseError::InvalidPayload {
+            reason: message.split(" at line ").next().unwrap_or_default().to_string(),
+            span: at..at + width,
+        }
+    })?;
+    let leading = text.len() - text.trim_start().len();
+    wire::check_raw(&payload).map_err(|reason| ParseError::InvalidPayload {
+        reason,
+        span: body.start + leading..body.start + text.trim_end().len(),
+    })?;
+    Ok(Command::Raw { payload })
 }
 
-fn get_param<'a>(params: &'a [(String, String)], key: &str) -> Option<&'a str> {
-    params.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+/// Reads the optional word after a keyword, as in `usb_commands <prefix>` and `help <command>`.
+fn optional_word(input: &str) -> Result<Option<String>, ParseError> {
+    let words: Vec<(usize, &str)> = input
+        .split_whitespace()
+        .map(|word| (word.as_ptr() as usize - input.as_ptr() as usize, word))
+        .collect();
+
+    match words.as_slice() {
+        [] | [_] => Ok(None),
+        [_, word] => Ok(Some(word.1.to_string())),
+        [_, _, (start, extra), ..] => Err(ParseError::UnexpectedToken {
+            expected: "end of input",
+            found: format!("'{}'", extra),
+            span: *start..start + extra.len(),
+        }),
+    }
 }
 
-fn parse_node_id(params: &[(String, String)]) -> Result<Option<u32>> {
-    if let Some(value) = get_param(params, "node_id") {
-        let id = value.parse::<u32>().map_err(|_| anyhow!("Invalid node_id: must be a positive integer"))?;
-        Ok(Some(id))
-    } else {
-        Ok(None)
-    }
+/// Alternative spellings of parameter keys, mapped to the key the parsers look up.
+const KEY_SYNONYMS: &[(&str, &str)] = &[("node", "node_id")];
+
+/// Maps a key as written to the name it is looked up by.
+fn canonical_key(key: &str) -> &str {
+    KEY_SYNONYMS
+        .iter()
+        .find(|(synonym, _)| key.eq_ignore_ascii_case(synonym))
+        .map_or(key, |(_, canonical)| canonical)
 }
 
-fn parse_iso_timestamp(s: &str) -> Result<DateTime<Utc>> {
-    // Try parsing with timezone first
-    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
-        return Ok(dt.with_timezone(&Utc));
-    }
+/// A single `key=value` pair together with where each half sits in the input.
+#[derive(Debug, Clone)]
+struct Param {
+    key: String,
+    key_span: Span,
+    value: String,
+    value_span: Span,
+    /// Whether the value was written as a quoted string rather than a bare word
+    quoted: bool,
+}
 
-    // Try parsing ISO 8601 with offset
-    if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%z") {
-        return Ok(dt.with_timezone(&Utc));
+impl Param {
+    /// A value given without a key. The key is filled in by `Args::resolve_positional`.
+    fn positional(value: String, span: Span, quoted: bool) -> Self {
+        Param {
+            key: String::new(),
+            key_span: span.clone(),
+            value,
+            value_span: span,
+            quoted,
+        }
     }
+}
+
+/// The parenthesised argument list of a command.
+#[derive(Debug, Clone)]
+struct Args {
+    params: Vec<Param>,
+    /// Values given without a key, in order, until `resolve_positional` names them
+    positional: Vec<Param>,
+    /// Covers the parentheses, used when a required parameter is absent
+    span: Span,
+}
 
-    if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M%z") {
-        return Ok(dt.with_timezone(&Utc));
+impl Args {
+    fn get(&self, key: &str) -> Option<&Param> {
+        self.params.iter().find(|p| canonical_key(&p.key).eq_ignore_ascii_case(key))
     }
 
-    // Try with explicit +/- offset format
-    if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%:z") {
-        return Ok(dt.with_timezone(&Utc));
+    /// Rejects parameters that `command` does not know and parameters given more than once.
+    fn check(&self, command: &'static str, allowed: &[&'static str]) -> Result<(), ParseError> {
+        for (idx, param) in self.params.iter().enumerate() {
+            let key = canonical_key(&param.key);
+            if !allowed.iter().any(|allowed| key.eq_ignore_ascii_case(allowed)) {
+                return Err(ParseError::UnknownParameter {
+                    command,
+                    name: param.key.clone(),
+                    suggestion: closest(&param.key, allowed.iter().copied()),
+                    span: param.key_span.clone(),
+                });
+            }
+            if self.params[..idx].iter().any(|p| canonical_key(&p.key).eq_ignore_ascii_case(key)) {
+                return Err(ParseError::DuplicateParameter {
+                    name: param.key.clone(),
+                    span: param.key_span.clone(),
+                });
+            }
+        }
+        Ok(())
     }
 
-    Err(anyhow!("Invalid ISO 8601 timestamp: {}", s))
-}
+    /// Names the positional values after `spec.positional` and moves them to `params`.
+    /// Values beyond the declared ones extend a `node_id` slot as a node list, so
+    /// `update_node(21, 22)` targets both nodes.
+    fn resolve_positional(&mut self, input: &str, spec: &'static CommandSpec) -> Result<(), ParseError> {
+        // `set_log_level(node_id, log_level=DEBUG)` is a key missing its value, not a value
+        let keys = spec.keys();
+        if let Some(item) = self.positional.iter().find(|item| !item.quoted && keys.iter().any(|key| canonical_key(&item.value).eq_ignore_ascii_case(key))) {
+            return Err(ParseError::DanglingKey {
+                key: item.value.clone(),
+                span: item.value_span.clone(),
+            });
+        }
+        if let Some(named) = self.params.first() {
+            if let Some(item) = self.positional.iter().find(|item| item.value_span.start > named.key_span.start) {
+                return Err(ParseError::PositionalAfterNamed {
+                    span: item.value_span.clone(),
+                });
+            }
+        }
 
-fn parse_set_update_interval(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
+        let mut items = std::mem::take(&mut self.positional);
+        let slots = spec.positional.len();
+        if items.len() > slots {
+            let surplus = items.len() - slots;
+            let Some(slot) = spec.positional.iter().position(|key| key == "node_id") else {
+                return Err(ParseError::TooManyArguments {
+                    command: &spec.name,
+                    max: slots,
+                    span: items[slots].value_span.start..items[items.len() - 1].value_span.end,
+                });
+            };
+            let list: Vec<Param> = items.drain(slot..=slot + surplus).collect();
+            let span = list[0].value_span.start..list[surplus].value_span.end;
+            let quoted = list.iter().any(|item| item.quoted);
+            items.insert(slot, Param::positional(input[span.clone()].to_string(), span, quoted));
+        }
 
-    // Reject node_id parameter - this command targets all probes
-    if get_param(&params, "node_id").is_some() {
-        return Err(anyhow!("set_update_interval does not accept node_id parameter"));
+        for (key, item) in spec.positional.iter().zip(items) {
+            if let Some(named) = self.get(key) {
+                return Err(ParseError::ConflictingArgument {
+                    name: key,
+                    span: named.key_span.clone(),
+                });
+            }
+            self.params.push(Param { key: key.clone(), ..item });
+        }
+        Ok(())
     }
 
-    let start_time_str = get_param(&params, "start_time").ok_or_else(|| anyhow!("Missing start_time parameter"))?;
-    let end_time_str = get_param(&params, "end_time").ok_or_else(|| anyhow!("Missing end_time parameter"))?;
-    let active_period_str = get_param(&params, "active_period").ok_or_else(|| anyhow!("Missing active_period parameter"))?;
-    let inactive_period_str = get_param(&params, "inactive_period").ok_or_else(|| anyhow!("Missing inactive_period parameter"))?;
-
-    let start_time = parse_iso_timestamp(start_time_str)?;
-    let end_time = parse_iso_timestamp(end_time_str)?;
-    let active_period = active_period_str
-        .parse::<u64>()
-        .map_err(|_| anyhow!("Invalid active_period: must be a positive integer"))?;
-    let inactive_period = inactive_period_str
-        .parse::<u64>()
-        .map_err(|_| anyhow!("Invalid inactive_period: must be a positive integer"))?;
+    fn require(&self, key: &'static str) -> Result<&Param, ParseError> {
+        self.get(key).ok_or_else(|| ParseError::MissingParameter {
+            name: key,
+            span: self.span.clone(),
+        })
+    }
+}
 
-    Ok(Command::SetUpdateInterval {
-        start_time,
-        end_time,
-        active_period,
-        inactive_period,
-    })
+/// Keys whose value may be a comma-separated list, as in `node_id=21,22,30-35`.
+const LIST_KEYS: &[&str] = &["node_id"];
+
+/// Extends a list value over its `, item` continuations and returns the full list text.
+/// A word counts as a list item unless it is followed by `=`, in which case it starts the next parameter.
+fn extend_list(input: &str, mut span: Span, tokens: &mut IntoIter<Token>) -> (String, Span) {
+    while let [Token {
+        kind: TokenKind::Comma, ..
+    }, Token {
+        kind: TokenKind::Word(_),
+        span: item_span,
+    }, rest @ ..] = tokens.as_slice()
+    {
+        if matches!(rest.first(), Some(Token { kind: TokenKind::Eq, .. })) {
+            break;
+        }
+        span.end = item_span.end;
+        tokens.nth(1);
+    }
+    (input[span.clone()].to_string(), span)
 }
 
-fn parse_set_log_level(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
-    let node_id = parse_node_id(&params)?;
+/// Parses `value, ..., key=value, ...` up to and including the closing parenthesis.
+/// `open` is the byte offset of the opening parenthesis.
+fn parse_args(
+    input: &str,
+    open: usize,
+    tokens: &mut IntoIter<Token>,
+) -> Result<Args, ParseError> {
+    let mut params = Vec::new();
+    let mut positional = Vec::new();
+    let unclosed = || ParseError::MissingClosingParen {
+        span: open..input.len(),
+    };
 
-    let log_level = get_param(&params, "log_level")
-        .ok_or_else(|| anyhow!("Missing log_level parameter"))?
-        .to_uppercase();
+    loop {
+        // A value followed by ',' or ')' is positional; anything else starts `key=value`
+        let ends_item = matches!(tokens.as_slice().get(1), Some(Token { kind: TokenKind::Comma | TokenKind::RParen, .. }));
+        let (key, key_span) = match tokens.next().ok_or_else(unclosed)? {
+            Token {
+                kind: TokenKind::RParen,
+                span,
+            } => {
+                return Ok(Args {
+                    params,
+                    positional,
+                    span: open..span.end,
+                })
+            }
+            Token {
+                kind: TokenKind::Word(value),
+                span,
+            } if ends_item => {
+                positional.push(Param::positional(value, span, false));
+                match end_of_item(tokens) {
+                    Some(end) => return Ok(Args { params, positional, span: open..end }),
+                    None => continue,
+                }
+            }
+            Token {
+                kind: TokenKind::Str(value),
+                span,
+            } if ends_item => {
+                positional.push(Param::positional(value, span, true));
+                match end_of_item(tokens) {
+                    Some(end) => return Ok(Args { params, positional, span: open..end }),
+                    None => continue,
+                }
+            }
+            Token {
+                kind: TokenKind::Word(key),
+                span,
+            } => (key, span),
+            token => {
+                return Err(ParseError::UnexpectedToken {
+                    expected: "parameter name",
+                    found: token.kind.describe(),
+                    span: token.span,
+                })
+            }
+        };
+
+        match tokens.next().ok_or_else(unclosed)? {
+            Token {
+                kind: TokenKind::Eq, ..
+            } => {}
+            token => {
+                return Err(ParseError::UnexpectedToken {
+                    expected: "'='",
+                    found: token.kind.describe(),
+                    span: token.span,
+                })
+            }
+        }
 
-    // Validate log level
-    match log_level.as_str() {
-        "TRACE" | "DEBUG" | "INFO" | "WARN" | "ERROR" => {}
-        _ => return Err(anyhow!("Invalid log_level: must be TRACE, DEBUG, INFO, WARN, or ERROR")),
-    }
+        let (value, value_span, quoted) = match tokens.next().ok_or_else(unclosed)? {
+            Token {
+                kind: TokenKind::Word(value),
+                span,
+            } => (value, span, false),
+            Token {
+                kind: TokenKind::Str(value),
+                span,
+            } => (value, span, true),
+            Token {
+                kind: TokenKind::Comma | TokenKind::RParen,
+                span,
+            } => {
+                return Err(ParseError::MissingValue {
+                    key,
+                    span: span.start..span.start,
+                })
+            }
+            token => {
+                return Err(ParseError::UnexpectedToken {
+                    expected: "parameter value",
+                    found: token.kind.describe(),
+                    span: token.span,
+                })
+            }
+        };
 
-    Ok(Command::SetLogLevel { node_id, log_level })
+        let (value, value_span) = if LIST_KEYS.iter().any(|k| canonical_key(&key).eq_ignore_ascii_case(k)) && !quoted {
+            extend_list(input, value_span, tokens)
+        } else {
+            (value, value_span)
+        };
+
+        params.push(Param {
+            key,
+            key_span,
+            value,
+            value_span,
+            quoted,
+        });
+
+        match tokens.next().ok_or_else(unclosed)? {
+            Token {
+                kind: TokenKind::Comma, ..
+            } => {}
+            Token {
+                kind: TokenKind::RParen,
+                span,
+            } => {
+                return Ok(Args {
+                    params,
+                    positional,
+                    span: open..span.end,
+                })
+            }
+            token => {
+                return Err(ParseError::UnexpectedToken {
+                    expected: "',' or ')'",
+                    found: token.kind.describe(),
+                    span: token.span,
+                })
+            }
+        }
+    }
 }
 
-fn parse_set_log_filter(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
-    let node_id = parse_node_id(&params)?;
+/// Consumes the ',' or ')' after a positional value, returning the end of the list at ')'.
+/// The caller has checked that one of them follows.
+fn end_of_item(tokens: &mut IntoIter<Token>) -> Option<usize> {
+    match tokens.next() {
+        Some(Token {
+            kind: TokenKind::RParen,
+            span,
+        }) => Some(span.end),
+        _ => None,
+    }
+}
 
-    let log_filter = get_param(&params, "log_filter")
-        .ok_or_else(|| anyhow!("Missing log_filter parameter"))?
-        .to_string();
+/// Parses an integer parameter. Quoted values are strings, so `node_id="21"` is rejected.
+fn parse_integer<T: std::str::FromStr>(param: &Param, name: &'static str) -> Result<T, ParseError> {
+    let invalid = |reason| ParseError::InvalidValue {
+        name,
+        reason,
+        span: param.value_span.clone(),
+    };
+    if param.quoted {
+        return Err(invalid("must be an unquoted integer"));
+    }
+    param.value.parse::<T>().map_err(|_| invalid("must be a positive integer"))
+}
 
-    Ok(Command::SetLogFilter { node_id, log_filter })
+fn parse_node_id(args: &Args, ctx: &ParseContext) -> Result<Option<NodeSelector>, ParseError> {
+    args.get("node_id")
+        .map(|param| {
+            if param.quoted {
+                return Err(ParseError::InvalidValue {
+                    name: "node_id",
+                    reason: "must be unquoted",
+                    span: param.value_span.clone(),
+                });
+            }
+            NodeSelector::parse(&param.value, &param.value_span, ctx.inventory)
+        })
+        .transpose()
 }
 
-fn parse_command_cmd(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
-    let node_id = parse_node_id(&params)?;
+/// Parses a duration parameter such as `active_period=1m30s` into seconds.
+fn parse_period(args: &Args, key: &'static str) -> Result<u64, ParseError> {
+    let param = args.require(key)?;
+    let invalid = |reason| ParseError::InvalidValue {
+        name: key,
+        reason,
+        span: param.value_span.clone(),
+    };
+    if param.quoted {
+        return Err(invalid("must be unquoted"));
+    }
+    time::parse_duration(&param.value).map_err(invalid)
+}
 
-    let command = get_param(&params, "command").ok_or_else(|| anyhow!("Missing command parameter"))?.to_string();
+/// Parses an absolute or relative timestamp. `start` is the start of the window, which
+/// `+<duration>` values are relative to.
+fn parse_timestamp(
+    args: &Args,
+    key: &'static str,
+    ctx: &ParseContext,
+    start: Option<DateTime<Utc>>,
+) -> Result<DateTime<Utc>, ParseError> {
+    let param = args.require(key)?;
+    let invalid = |reason| ParseError::InvalidValue {
+        name: key,
+        reason,
+        span: param.value_span.clone(),
+    };
+    if let Some(result) = time::parse_relative(&param.value, ctx.now(), start, ctx.timezone) {
+        return result.map_err(invalid);
+    }
+    // Point at the offending part when the value was written as a bare word
+    let inner_span = |span: Span| {
+        if param.quoted {
+            param.value_span.clone()
+        } else {
+            param.value_span.start + span.start..param.value_span.start + span.end
+        }
+    };
+    time::parse_absolute(&param.value, ctx.timezone).map_err(|e| match e {
+        TimestampError::Malformed { expected, span } => ParseError::InvalidTimestamp {
+            value: param.value.clone(),
+            expected,
+            span: inner_span(span),
+        },
+        TimestampError::UnknownZone { name, suggestion, start } => {
+            let span = inner_span(start..start + name.len());
+            ParseError::UnknownTimeZone { name, suggestion, span }
+        }
+        TimestampError::Invalid(reason) => invalid(reason),
+    })
+}
 
-    Ok(Command::Command { node_id, command })
+/// Reads a value that ends up on a node's serial console.
+fn parse_node_string(args: &Args, name: &'static str, ctx: &ParseContext) -> Result<String, ParseError> {
+    let param = args.require(name)?;
+    if let Err(reason) = check_node_string(&param.value, ctx.allow_control_chars) {
+        return Err(ParseError::InvalidValue {
+            name,
+            reason,
+            span: param.value_span.clone(),
+        });
+    }
+    Ok(param.value.clone())
 }
 
-fn parse_update_node(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
-    let node_id = parse_node_id(&params)?;
+/// Longest `run_command` or `log_filter` value, in bytes, a node's serial console accepts.
+pub const MAX_NODE_STRING_LEN: usize = 256;
 
-    Ok(Command::UpdateNode { node_id })
+/// Zero-width and bidirectional formatting characters, which print as nothing (or reorder
+/// the text around them) but still reach the node.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
 }
 
-fn parse_update_probe(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
-    let node_id = parse_node_id(&params)?;
+/// Checks a value the probe writes to a node's serial console. The probe appends CRLF to
+/// `run_command` values, so a line break inside one would start a second command.
+pub fn check_node_string(value: &str, allow_control_chars: bool) -> Result<(), &'static str> {
+    if !allow_control_chars {
+        if value.contains(['\r', '\n']) {
+            return Err("must not contain line breaks, which the node reads as the end of a command (use --allow-control-chars to send them)");
+        }
+        if value.chars().any(char::is_control) {
+            return Err("must not contain control characters (use --allow-control-chars to send them)");
+        }
+        if value.chars().any(is_invisible) {
+            return Err("must not contain invisible formatting characters (use --allow-control-chars to send them)");
+        }
+    }
+    if value.len() > MAX_NODE_STRING_LEN {
+        return Err("must be at most 256 bytes");
+    }
+    Ok(())
+}
 
-    Ok(Command::UpdateProbe { node_id })
+/// Applies `check_node_string` to a command that did not come through the parser, such as
+/// `--json` input. Errors point at the rejected value in `source`, the JSON the command was
+/// read from, or at all of it if the value is written differently there.
+pub fn check_node_strings(command: &Command, ctx: &ParseContext, source: &str) -> Result<(), ParseError> {
+    let strings: Vec<(&'static str, &str)> = match command {
+        Command::SetLogFilter { log_filter, .. } | Command::PreviewFilter { log_filter, .. } => vec![("log_filter", log_filter)],
+        Command::Command { command, .. } => vec![("command", command)],
+        Command::Custom { name, params, .. } => {
+            let spec = ctx.registry().find(name);
+            params
+                .iter()
+                .filter(|(key, _)| spec.and_then(|spec| spec.param(key)).is_some_and(|param| param.kind == ParamType::Serial))
+                .filter_map(|(key, value)| match value {
+                    ArgValue::Text(text) => Some((*key, text.as_str())),
+                    _ => None,
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    for (name, value) in strings {
+        check_node_string(value, ctx.allow_control_chars).map_err(|reason| {
+            let quoted = serde_json::to_string(value).unwrap_or_default();
+            let span = match source.find(&quoted) {
+                Some(start) => start..start + quoted.len(),
+                None => 0..source.len(),
+            };
+            ParseError::InvalidValue { name, reason, span }
+        })?;
+    }
+    Ok(())
 }
 
-fn parse_reboot_probe(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
-    let node_id = parse_node_id(&params)?;
+/// Parses the argument list of a registry command and builds the command.
+fn parse_with_spec(spec: &'static CommandSpec, args: &Args, ctx: &ParseContext) -> Result<Command, ParseError> {
+    args.check(&spec.name, &spec.keys())?;
+
+    let node_id = match spec.target {
+        Target::None => None,
+        Target::Optional => parse_node_id(args, ctx)?,
+        Target::Required => Some(parse_node_id(args, ctx)?.ok_or_else(|| ParseError::RequiredParameter {
+            command: &spec.name,
+            name: "node_id",
+            span: args.span.clone(),
+        })?),
+    };
 
-    Ok(Command::RebootProbe { node_id })
-}
+    let mut values: Vec<(&'static str, ArgValue)> = Vec::new();
+    for param in &spec.params {
+        if !param.required && args.get(&param.name).is_none() {
+            continue;
+        }
+        let name = param.name.as_str();
+        let value = match param.kind {
+            ParamType::Integer => ArgValue::Integer(parse_bounded(args, param)?),
+            ParamType::String => ArgValue::Text(args.require(name)?.value.clone()),
+            ParamType::Serial => ArgValue::Text(parse_node_string(args, name, ctx)?),
+            ParamType::Timestamp => {
+                let start = values.iter().find_map(|(key, value)| match value {
+                    ArgValue::Timestamp(time) if param.relative_to.as_deref() == Some(*key) => Some(*time),
+                    _ => None,
+                });
+                ArgValue::Timestamp(parse_timestamp(args, name, ctx, start)?)
+            }
+            ParamType::Duration => ArgValue::Duration(parse_period(args, name)?),
+            ParamType::Choice => ArgValue::Text(parse_choice(args, param)?),
+        };
+        values.push((name, value));
+    }
 
-fn parse_start_measurement(params_str: &str) -> Result<Command> {
-    let params = parse_params(params_str)?;
+    build(spec, args, node_id, values)
+}
 
-    let node_id = parse_node_id(&params)?.ok_or_else(|| anyhow!("node_id is required for start_measurement command"))?;
+/// Parses an `integer` parameter, checking it against `max`.
+fn parse_bounded(args: &Args, param: &'static ParamSpec) -> Result<u64, ParseError> {
+    let arg = args.require(&param.name)?;
+    let value = parse_integer(arg, &param.name)?;
+    if param.max.is_some_and(|max| value > max) {
+        return Err(ParseError::InvalidValue {
+            name: &param.name,
+            reason: &param.reason,
+            span: arg.value_span.clone(),
+        });
+    }
+    Ok(value)
+}
 
-    let sequence_str = get_param(&params, "sequence").ok_or_else(|| anyhow!("Missing sequence parameter"))?;
+/// Parses a `choice` parameter into the spelling the registry lists.
+fn parse_choice(args: &Args, param: &'static ParamSpec) -> Result<String, ParseError> {
+    let arg = args.require(&param.name)?;
+    param
+        .values
+        .iter()
+        .find(|value| value.eq_ignore_ascii_case(&arg.value))
+        .cloned()
+        .ok_or_else(|| ParseError::InvalidValue {
+            name: &param.name,
+            reason: &param.reason,
+            span: arg.value_span.clone(),
+        })
+}
 
-    let sequence = sequence_str
-        .parse::<u32>()
-        .map_err(|_| anyhow!("Invalid sequence: must be a positive integer"))?;
+/// Turns parsed values into the typed command for built-in entries of `commands.toml`, and
+/// into `Command::Custom` for the rest. `Registry::check` makes sure the built-in entries list
+/// the parameters matched here by position, with the same names and types, in this order.
+fn build(spec: &'static CommandSpec, args: &Args, node_id: Option<NodeSelector>, values: Vec<(&'static str, ArgValue)>) -> Result<Command, ParseError> {
+    use ArgValue::{Duration, Integer, Text, Timestamp};
 
-    Ok(Command::StartMeasurement { node_id, sequence })
+    let command = match (spec.name.as_str(), values.as_slice()) {
+        // Without node_id every probe gets the new schedule
+        ("set_update_interval", [(_, Timestamp(start_time)), (_, Timestamp(end_time)), (_, Duration(active_period)), (_, Duration(inactive_period))]) => {
+            Command::SetUpdateInterval {
+                node_id,
+                start_time: *start_time,
+                end_time: *end_time,
+                active_period: *active_period,
+                inactive_period: *inactive_period,
+            }
+        }
+        ("set_log_level", [(_, Text(log_level))]) => Command::SetLogLevel {
+            node_id,
+            log_level: log_level.clone(),
+        },
+        ("set_log_filter", [(_, Text(log_filter))]) => Command::SetLogFilter {
+            node_id,
+            log_filter: log_filter.clone(),
+        },
+        ("run_command", [(_, Text(command))]) => Command::Command {
+            node_id,
+            command: command.clone(),
+        },
+        ("update_node", []) => Command::UpdateNode { node_id },
+        ("update_probe", []) => Command::UpdateProbe { node_id },
+        ("reboot_probe", []) => Command::RebootProbe { node_id },
+        ("start_measurement", [(key, Integer(sequence))]) => Command::StartMeasurement {
+            node_id: node_id.ok_or_else(|| ParseError::RequiredParameter {
+                command: &spec.name,
+                name: "node_id",
+                span: args.span.clone(),
+            })?,
+            sequence: u32::try_from(*sequence).map_err(|_| ParseError::InvalidValue {
+                name: key,
+                reason: "must be at most 4294967295",
+                span: args.get(key).map_or_else(|| args.span.clone(), |arg| arg.value_span.clone()),
+            })?,
+        },
+        ("preview_filter", [(_, Text(log_filter)), (_, Text(file))]) => Command::PreviewFilter {
+            log_filter: log_filter.clone(),
+            file: file.clone(),
+        },
+        (name, _) if registry::TYPED_COMMANDS.iter().any(|(typed, _, _)| *typed == name) => {
+            return Err(ParseError::InvalidDefinition {
+                command: &spec.name,
+                span: args.span.clone(),
+            })
+        }
+        _ => Command::Custom {
+            name: &spec.name,
+            node_id,
+            params: values,
+        },
+    };
+    Ok(command)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    fn parse_command(input: &str) -> Result<Command, ParseError> {
+        parse_command_with(input, &ParseContext::default())
+    }
 
     #[test]
     fn test_parse_quit_commands() {
@@ -405,7 +1255,7 @@ mod tests {
         let cmd = parse_command("set_log_level(node_id=21, log_level=DEBUG)").unwrap();
         match cmd {
             Command::SetLogLevel { node_id, log_level } => {
-                assert_eq!(node_id, Some(21));
+                assert_eq!(node_id, Some(21.into()));
                 assert_eq!(log_level, "DEBUG");
             }
             _ => panic!("Wrong command type"),
@@ -417,7 +1267,7 @@ mod tests {
         let cmd = parse_command("update_node(node_id=42)").unwrap();
         match cmd {
             Command::UpdateNode { node_id } => {
-                assert_eq!(node_id, Some(42));
+                assert_eq!(node_id, Some(42.into()));
             }
             _ => panic!("Wrong command type"),
         }
@@ -434,12 +1284,124 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_set_update_interval_with_node_id() {
+        let cmd = parse_command("set_update_interval(node_id=21, start_time=2026-10-23T15:30+01, end_time=2026-10-23T18:00+01, active_period=10, inactive_period=300)").unwrap();
+        assert_eq!(cmd.nodes(), Some(&21.into()));
+
+        let cmd = parse_command("set_update_interval(start_time=2026-10-23T15:30+01, end_time=2026-10-23T18:00+01, active_period=10, inactive_period=300)").unwrap();
+        assert_eq!(cmd.nodes(), None);
+
+        let cmd = parse_command("set_update_interval(node_id=21,22, start_time=2026-10-23T15:30+01, end_time=+1h, active_period=10, inactive_period=300)").unwrap();
+        let expanded = cmd.expand(&[]).unwrap();
+        assert_eq!(expanded.len(), 2);
+        assert_eq!(expanded[1].to_json().unwrap()["parameters"]["node id"], 22);
+        assert_eq!(expanded[1].to_json().unwrap()["parameters"]["end_time"], "2026-10-23T15:30:00+00:00");
+    }
+
+    #[test]
+    fn test_parse_preview_filter() {
+        let expected = Command::PreviewFilter {
+            log_filter: "[ERROR]".to_string(),
+            file: "logs/collector.log".to_string(),
+        };
+        assert_eq!(parse_command(r#"preview_filter(log_filter="[ERROR]", file=logs/collector.log)"#).unwrap(), expected);
+        assert_eq!(parse_command(r#"Preview-Filter(file="logs/collector.log", log_filter="[ERROR]")"#).unwrap(), expected);
+        assert!(parse_command(r#"preview-filter(log_filter="x")"#).unwrap_err().to_string().contains("Missing file"));
+        assert!(expected.to_json().is_err());
+    }
+
+    #[test]
+    fn test_node_strings_reject_control_characters() {
+        let reason = |input: &str| match parse_command(input).unwrap_err() {
+            ParseError::InvalidValue { reason, .. } => reason,
+            err => panic!("unexpected error {:?}", err),
+        };
+        for input in [
+            r#"run_command(node_id=21, command="/LD\x0D\x0A/BS")"#,
+            r#"run_command(node_id=21, command="/LD\n/BS")"#,
+            r#"set_log_filter(node_id=21, log_filter="radio\x0D")"#,
+        ] {
+            assert!(reason(input).starts_with("must not contain line breaks"), "{}", input);
+        }
+        for input in [
+            r#"run_command(node_id=21, command="/LD\t")"#,
+            r#"run_command(node_id=21, command="\x00/LD")"#,
+            r#"set_log_filter(node_id=21, log_filter="\x07")"#,
+            r#"set_log_filter(node_id=21, log_filter="\x7F")"#,
+            "set_log_filter(node_id=21, log_filter=\"\u{85}\")",
+        ] {
+            assert!(reason(input).starts_with("must not contain control characters"), "{}", input);
+        }
+        for input in ["run_command(node_id=21, command=\"/L\u{200B}D\")", "set_log_filter(log_filter=\"\u{202E}RORRE\")"] {
+            assert!(reason(input).starts_with("must not contain invisible formatting characters"), "{}", input);
+        }
+
+        let input = r#"set_log_filter(node_id=21, log_filter="a\nb")"#;
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "log_filter", .. }));
+        assert_eq!(&input[err.span()], r#""a\nb""#);
+        // preview_filter checks the filter the same way set_log_filter does
+        assert!(parse_command(r#"preview_filter(log_filter="a\nb", file=x.log)"#).is_err());
+        // Printable non-ASCII text is fine
+        assert!(parse_command("set_log_filter(log_filter=\"température\")").is_ok());
+    }
+
+    #[test]
+    fn test_node_string_length_and_opt_in() {
+        let longest = "x".repeat(MAX_NODE_STRING_LEN);
+        assert!(parse_command(&format!("run_command(node_id=21, command={})", longest)).is_ok());
+        let err = parse_command(&format!("run_command(node_id=21, command={}x)", longest)).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid command: must be at most 256 bytes");
+
+        let ctx = ParseContext {
+            allow_control_chars: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            parse_command_with(r#"run_command(node_id=21, command="/LD\x0D\x0A")"#, &ctx).unwrap(),
+            Command::Command {
+                node_id: Some(21.into()),
+                command: "/LD\r\n".to_string(),
+            }
+        );
+        // The opt-in does not lift the length limit
+        assert!(parse_command_with(&format!("set_log_filter(log_filter={}x)", longest), &ctx).is_err());
+
+        // --json input skips the parser and is checked separately
+        let command = Command::SetLogFilter {
+            node_id: None,
+            log_filter: "a\r\nb".to_string(),
+        };
+        let source = r#"{"command": "set_log_filter", "parameters": {"log_filter": "a\r\nb"}}"#;
+        let err = check_node_strings(&command, &ParseContext::default(), source).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "log_filter", .. }));
+        assert_eq!(&source[err.span()], r#""a\r\nb""#);
+        let source = r#"{"command": "set_log_filter", "parameters": {"log_filter": "a\u000d\u000ab"}}"#;
+        assert_eq!(check_node_strings(&command, &ParseContext::default(), source).unwrap_err().span(), 0..source.len());
+        assert_eq!(check_node_strings(&command, &ctx, source), Ok(()));
+        assert_eq!(check_node_strings(&Command::UpdateNode { node_id: None }, &ParseContext::default(), "{}"), Ok(()));
+    }
+
+    #[test]
+    fn test_parse_usb_commands() {
+        assert_eq!(parse_command("usb_commands").unwrap(), Command::UsbCommands { prefix: None });
+        assert_eq!(
+            parse_command("  USB_COMMANDS /L ").unwrap(),
+            Command::UsbCommands {
+                prefix: Some("/L".to_string())
+            }
+        );
+        let input = "usb_commands /L extra";
+        assert_eq!(&input[parse_command(input).unwrap_err().span()], "extra");
+    }
+
     #[test]
     fn test_parse_start_measurement() {
         let cmd = parse_command("start_measurement(node_id=21, sequence=42)").unwrap();
         match cmd {
             Command::StartMeasurement { node_id, sequence } => {
-                assert_eq!(node_id, 21);
+                assert_eq!(node_id, 21.into());
                 assert_eq!(sequence, 42);
             }
             _ => panic!("Wrong command type"),
@@ -452,4 +1414,707 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("node_id is required"));
     }
+
+    #[test]
+    fn test_parse_error_kinds() {
+        assert!(matches!(
+            parse_command("set_log_level(node_id=21").unwrap_err(),
+            ParseError::MissingClosingParen { .. }
+        ));
+        assert!(matches!(
+            parse_command("frobnicate()").unwrap_err(),
+            ParseError::UnknownCommand { name, .. } if name == "frobnicate"
+        ));
+        assert!(matches!(
+            parse_command("set_log_level").unwrap_err(),
+            ParseError::MissingArguments { command: "set_log_level", .. }
+        ));
+        assert!(matches!(
+            parse_command("set_log_level(node_id=21)").unwrap_err(),
+            ParseError::MissingParameter { name: "log_level", .. }
+        ));
+        assert!(matches!(
+            parse_command("start_measurement(sequence=1)").unwrap_err(),
+            ParseError::RequiredParameter { name: "node_id", .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_error_spans_point_at_offending_token() {
+        let input = "  set_log_level(node_id=2x, log_level=DEBUG)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "node_id", .. }));
+        assert_eq!(&input[err.span()], "2x");
+
+        let input = "set_update_interval(start_time=2025-10-23T15:30:00+01:00, end_time=soon, active_period=1, inactive_period=2)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidTimestamp { .. }));
+        assert_eq!(&input[err.span()], "soon");
+
+        let input = "set_update_interval(start_time=2025-13-23T15:30+01, end_time=now, active_period=1, inactive_period=2)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidTimestamp { expected: "a month (MM, 01-12)", .. }));
+        assert_eq!(&input[err.span()], "13");
+
+        let input = "bogus(node_id=1)";
+        assert_eq!(&input[parse_command(input).unwrap_err().span()], "bogus");
+    }
+
+    #[test]
+    fn test_render_underlines_span() {
+        let input = "set_log_level(node_id=21, log_level=LOUD)";
+        let rendered = parse_command(input).unwrap_err().render(input);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "Parse error: Invalid log_level: must be TRACE, DEBUG, INFO, WARN, or ERROR");
+        assert_eq!(lines[1], format!("  | {}", input));
+        assert_eq!(lines[2], format!("  | {}^^^^", " ".repeat(36)));
+
+        // An error from the second command of a line is underlined within the whole line
+        let line = format!("update_node(); {}", input);
+        let rendered = parse_command(input).unwrap_err().offset(15).render(&line);
+        assert_eq!(rendered.lines().nth(2).unwrap(), format!("  | {}^^^^", " ".repeat(51)));
+    }
+
+    #[test]
+    fn test_render_span_inside_character() {
+        let input = "set_log_filter(node_id=21, log_filter=\"é\")";
+        let inside = input.find('é').unwrap() + 1;
+        let err = ParseError::InvalidValue { name: "log_filter", reason: "test", span: inside..inside + 1 };
+        assert_eq!(err.render(input).lines().nth(2).unwrap(), format!("  | {}^", " ".repeat(39)));
+        let err = ParseError::InvalidValue { name: "log_filter", reason: "test", span: inside - 1..inside };
+        assert_eq!(err.render(input).lines().nth(2).unwrap(), format!("  | {}^", " ".repeat(39)));
+        let err = ParseError::InvalidValue { name: "log_filter", reason: "test", span: 100..200 };
+        assert_eq!(err.render(input).lines().nth(2).unwrap(), format!("  | {}^", " ".repeat(42)));
+    }
+
+    #[test]
+    fn test_quoted_values_are_unquoted() {
+        let cmd = parse_command(r#"set_log_filter(node_id=21, log_filter="[ERROR]")"#).unwrap();
+        match cmd {
+            Command::SetLogFilter { log_filter, .. } => assert_eq!(log_filter, "[ERROR]"),
+            _ => panic!("Wrong command type"),
+        }
+
+        let cmd = parse_command(r#"run_command(command="say \"hi, there\"\x21")"#).unwrap();
+        match cmd {
+            Command::Command { command, .. } => assert_eq!(command, "say \"hi, there\"!"),
+            _ => panic!("Wrong command type"),
+        }
+
+        let cmd = parse_command(r#"set_log_filter(log_filter="")"#).unwrap();
+        assert!(matches!(cmd, Command::SetLogFilter { log_filter, .. } if log_filter.is_empty()));
+    }
+
+    #[test]
+    fn test_malformed_parameter_lists() {
+        let input = "set_log_level(node_id, log_level=DEBUG)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::DanglingKey { ref key, .. } if key == "node_id"));
+        assert_eq!(&input[err.span()], "node_id");
+
+        assert!(matches!(
+            parse_command("set_log_filter(log_filter=)").unwrap_err(),
+            ParseError::MissingValue { .. }
+        ));
+        assert!(matches!(
+            parse_command(r#"set_log_filter(log_filter="[ERROR])"#).unwrap_err(),
+            ParseError::UnterminatedString { .. }
+        ));
+        assert!(matches!(
+            parse_command("set_log_level(node_id=21 log_level=DEBUG)").unwrap_err(),
+            ParseError::UnexpectedToken { .. }
+        ));
+        assert!(matches!(
+            parse_command("update_node(node_id=1) extra").unwrap_err(),
+            ParseError::UnexpectedToken { expected: "end of input", .. }
+        ));
+        assert!(matches!(
+            parse_command(r#"update_node(node_id="21")"#).unwrap_err(),
+            ParseError::InvalidValue { name: "node_id", .. }
+        ));
+    }
+
+    #[test]
+    fn test_unknown_parameter_suggests_closest_key() {
+        let input = "set_log_level(node_id=21, loglevel=DEBUG)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::UnknownParameter { command: "set_log_level", suggestion: Some("log_level"), .. }
+        ));
+        assert_eq!(&input[err.span()], "loglevel");
+        assert!(err.to_string().ends_with("(did you mean log_level?)"));
+
+        assert!(matches!(
+            parse_command("update_node(colour=blue)").unwrap_err(),
+            ParseError::UnknownParameter { suggestion: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_parameter_rejected() {
+        let input = "set_log_level(node_id=21, log_level=DEBUG, NODE_ID=22)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::DuplicateParameter { .. }));
+        assert_eq!(&input[err.span()], "NODE_ID");
+    }
+
+    #[test]
+    fn test_unknown_command_suggests_closest_name() {
+        assert!(matches!(
+            parse_command("set_log_levle(log_level=INFO)").unwrap_err(),
+            ParseError::UnknownCommand { suggestion: Some("set_log_level"), .. }
+        ));
+        assert!(matches!(
+            parse_command("quti").unwrap_err(),
+            ParseError::UnknownCommand { suggestion: Some("quit"), .. }
+        ));
+        assert!(matches!(
+            parse_command("hello()").unwrap_err(),
+            ParseError::UnknownCommand { suggestion: Some("help"), .. }
+        ));
+        assert!(matches!(
+            parse_command("greetings()").unwrap_err(),
+            ParseError::UnknownCommand { suggestion: None, .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_node_lists() {
+        let input = "set_log_level(node_id=21, 22,30-35, log_level=DEBUG)";
+        let cmd = parse_command(input).unwrap();
+        let nodes = cmd.nodes().unwrap();
+        assert_eq!(nodes.single(), None);
+        assert_eq!(nodes.resolve(&[]).unwrap(), vec![21, 22, 30, 31, 32, 33, 34, 35]);
+
+        let expanded = cmd.expand(&[]).unwrap();
+        assert_eq!(expanded.len(), 8);
+        assert_eq!(expanded[2].to_json().unwrap()["parameters"]["node id"], 30);
+        assert_eq!(expanded[2].to_json().unwrap()["parameters"]["log_level"], "DEBUG");
+
+        let cmd = parse_command("update_node(node_id=!24)").unwrap();
+        let ids: Vec<_> = cmd.expand(&[21, 24, 25]).unwrap().iter().map(|c| c.to_json().unwrap()["parameters"]["node id"].clone()).collect();
+        assert_eq!(ids, vec![21, 25]);
+        assert!(cmd.to_json().is_err());
+    }
+
+    #[test]
+    fn test_node_list_errors() {
+        // A bare word after a node list is a list item unless it is followed by '='
+        let input = "set_log_level(node_id=21, log_level)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownNode { .. }));
+        assert_eq!(&input[err.span()], "log_level");
+        let err = parse_command("set_log_level(log_level=DEBUG, node_id)").unwrap_err();
+        assert!(matches!(err, ParseError::DanglingKey { .. }));
+
+        let input = "update_node(node_id=21,3-x)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "node_id", .. }));
+        assert_eq!(&input[err.span()], "3-x");
+    }
+
+    #[test]
+    fn test_positional_arguments() {
+        assert_eq!(parse_command("set_log_level(21, DEBUG)").unwrap(), parse_command("set_log_level(node_id=21, log_level=DEBUG)").unwrap());
+        assert_eq!(parse_command("start_measurement(21, 42)").unwrap(), parse_command("start_measurement(node_id=21, sequence=42)").unwrap());
+        assert_eq!(parse_command("update_node(21)").unwrap(), parse_command("update_node(node_id=21)").unwrap());
+        assert_eq!(parse_command(r#"set_log_filter(21, "[ERROR]")"#).unwrap().to_string(), r#"set_log_filter(node_id=21, log_filter="[ERROR]")"#);
+        assert_eq!(parse_command("set_log_level(21, log_level=warn)").unwrap().to_string(), "set_log_level(node_id=21, log_level=WARN)");
+
+        // Extra values extend the node list
+        assert_eq!(parse_command("update_node(21, 22, 30-31)").unwrap().expand(&[]).unwrap().len(), 4);
+        assert_eq!(parse_command("set_log_level(21, 22, DEBUG)").unwrap().to_string(), "set_log_level(node_id=21,22, log_level=DEBUG)");
+        let input = r#"update_node(21, "22")"#;
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "node_id", .. }));
+        assert_eq!(&input[err.span()], r#"21, "22""#);
+
+        let input = "set_log_level(log_level=DEBUG, 21)";
+        let err = parse_command(input).unwrap_err();
+        assert_eq!(err.to_string(), "Positional arguments must come before named ones");
+        assert_eq!(&input[err.span()], "21");
+
+        let input = "set_log_level(21, DEBUG, node_id=22)";
+        let err = parse_command(input).unwrap_err();
+        assert_eq!(err.to_string(), "node_id is given both by position and by name");
+        assert_eq!(&input[err.span()], "node_id");
+        let err = parse_command("set_log_level(21, DEBUG, node=22)").unwrap_err();
+        assert!(matches!(err, ParseError::ConflictingArgument { name: "node_id", .. }));
+
+        let input = "preview_filter(radio, a.log, b.log)";
+        let err = parse_command(input).unwrap_err();
+        assert_eq!(err.to_string(), "preview_filter takes at most 2 positional arguments");
+        assert_eq!(&input[err.span()], "b.log");
+        assert!(matches!(parse_command("start_measurement(21)").unwrap_err(), ParseError::MissingParameter { name: "sequence", .. }));
+    }
+
+    #[test]
+    fn test_node_names_resolve_with_context() {
+        let inventory = vec![NodeInfo {
+            id: 21,
+            name: Some("roof-east".to_string()),
+            location: None,
+            tags: vec!["rooftop".to_string()],
+            probe_hostname: None,
+        }];
+        let ctx = ParseContext {
+            inventory: &inventory,
+            ..Default::default()
+        };
+
+        let cmd = parse_command_with("set_log_level(node_id=roof-east, log_level=INFO)", &ctx).unwrap();
+        assert_eq!(cmd.nodes(), Some(&21.into()));
+
+        let cmd = parse_command_with("reboot_probe(node=@rooftop)", &ctx).unwrap();
+        assert_eq!(cmd.nodes(), Some(&21.into()));
+
+        let err = parse_command_with("reboot_probe(node=@rooftop, node_id=3)", &ctx).unwrap_err();
+        assert!(matches!(err, ParseError::DuplicateParameter { .. }));
+
+        assert!(matches!(
+            parse_command("update_node(node_id=roof-east)").unwrap_err(),
+            ParseError::UnknownNode { .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_nodes_command() {
+        assert!(matches!(parse_command("nodes").unwrap(), Command::Nodes { name: None }));
+        assert!(matches!(
+            parse_command("  NODES show roof-east ").unwrap(),
+            Command::Nodes { name: Some(name) } if name == "roof-east"
+        ));
+
+        let input = "nodes list";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedToken { .. }));
+        assert_eq!(&input[err.span()], "list");
+        assert!(parse_command("nodes show").is_err());
+        assert!(parse_command("nodes show a b").is_err());
+    }
+
+    #[test]
+    fn test_display_is_canonical() {
+        let ctx = ParseContext {
+            allow_control_chars: true,
+            ..Default::default()
+        };
+        let cmd = parse_command_with(r#"SET_LOG_FILTER( Node = 22,21 , log_filter="a \"b\"\x01" )"#, &ctx).unwrap();
+        assert_eq!(cmd.to_string(), r#"set_log_filter(node_id=22,21, log_filter="a \"b\"\x01")"#);
+
+        let cmd = parse_command(
+            "set_update_interval(inactive_period=300, active_period=60, end_time=2025-10-23T18:00:00+02:00, start_time=2025-10-23T15:30:00.5+01:00)",
+        )
+        .unwrap();
+        assert_eq!(
+            cmd.to_string(),
+            "set_update_interval(start_time=2025-10-23T14:30:00.500Z, end_time=2025-10-23T16:00:00Z, active_period=60, inactive_period=300)"
+        );
+
+        assert_eq!(parse_command("set_log_level(log_level=debug)").unwrap().to_string(), "set_log_level(log_level=DEBUG)");
+        assert_eq!(parse_command("Update_Probe").unwrap().to_string(), "update_probe()");
+        assert_eq!(parse_command("nodes  SHOW roof-east").unwrap().to_string(), "nodes show roof-east");
+        assert_eq!(parse_command("BYE").unwrap().to_string(), "quit");
+    }
+
+    fn arb_nodes() -> impl Strategy<Value = NodeSelector> {
+        let item = prop_oneof![
+            1 => Just("*".to_string()),
+            4 => (any::<u32>(), 0u32..1024, any::<bool>()).prop_map(|(start, len, exclude)| {
+                let end = start.saturating_add(len);
+                let bang = if exclude { "!" } else { "" };
+                if start == end {
+                    format!("{}{}", bang, start)
+                } else {
+                    format!("{}{}-{}", bang, start, end)
+                }
+            }),
+        ];
+        prop::collection::vec(item, 1..4).prop_map(|items| {
+            let text = items.join(",");
+            NodeSelector::parse(&text, &(0..text.len()), &[]).unwrap()
+        })
+    }
+
+    fn arb_time() -> impl Strategy<Value = DateTime<Utc>> {
+        (0i64..4_102_444_800, 0u32..1_000_000_000).prop_map(|(secs, nanos)| DateTime::from_timestamp(secs, nanos).unwrap())
+    }
+
+    fn arb_command() -> impl Strategy<Value = Command> {
+        let level = prop::sample::select(vec!["TRACE", "DEBUG", "INFO", "WARN", "ERROR"]).prop_map(str::to_string);
+        let nodes = || proptest::option::of(arb_nodes());
+        // Any characters, control characters included, up to MAX_NODE_STRING_LEN bytes
+        let node_string = || proptest::collection::vec(any::<char>(), 0..=MAX_NODE_STRING_LEN / 4).prop_map(String::from_iter);
+        prop_oneof![
+            (nodes(), arb_time(), arb_time(), any::<u64>(), any::<u64>()).prop_map(
+                |(node_id, start_time, end_time, active_period, inactive_period)| Command::SetUpdateInterval {
+                    node_id,
+                    start_time,
+                    end_time,
+                    active_period,
+                    inactive_period,
+                }
+            ),
+            (nodes(), level).prop_map(|(node_id, log_level)| Command::SetLogLevel { node_id, log_level }),
+            (nodes(), node_string()).prop_map(|(node_id, log_filter)| Command::SetLogFilter { node_id, log_filter }),
+            (nodes(), node_string()).prop_map(|(node_id, command)| Command::Command { node_id, command }),
+            nodes().prop_map(|node_id| Command::UpdateNode { node_id }),
+            nodes().prop_map(|node_id| Command::UpdateProbe { node_id }),
+            nodes().prop_map(|node_id| Command::RebootProbe { node_id }),
+            (arb_nodes(), any::<u32>()).prop_map(|(node_id, sequence)| Command::StartMeasurement { node_id, sequence }),
+            (node_string(), any::<String>()).prop_map(|(log_filter, file)| Command::PreviewFilter { log_filter, file }),
+            proptest::option::of("/[A-Z]{0,3}").prop_map(|prefix| Command::UsbCommands { prefix }),
+            proptest::option::of("[a-z][a-z0-9-]{0,15}").prop_map(|name| Command::Nodes { name }),
+            Just(Command::Quit),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_display_round_trips(cmd in arb_command()) {
+            let ctx = ParseContext {
+                allow_control_chars: true,
+                ..Default::default()
+            };
+            prop_assert_eq!(parse_command_with(&cmd.to_string(), &ctx).unwrap(), cmd);
+        }
+    }
+
+    #[test]
+    fn test_relative_times_and_durations() {
+        let now = DateTime::parse_from_rfc3339("2025-10-23T12:00:00.75Z").unwrap().with_timezone(&Utc);
+        let ctx = ParseContext {
+            now: Some(now),
+            ..Default::default()
+        };
+        let cmd = parse_command_with(
+            "set_update_interval(start_time=now+30m, end_time=+PT2H, active_period=1m30s, inactive_period=PT1H)",
+            &ctx,
+        )
+        .unwrap();
+        assert_eq!(
+            cmd.to_string(),
+            "set_update_interval(start_time=2025-10-23T12:30:00Z, end_time=2025-10-23T14:30:00Z, active_period=90, inactive_period=3600)"
+        );
+
+        let input = "set_update_interval(start_time=+1h, end_time=now, active_period=1, inactive_period=1)";
+        let err = parse_command_with(input, &ctx).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "start_time", .. }));
+        assert_eq!(&input[err.span()], "+1h");
+
+        let input = "set_update_interval(start_time=now, end_time=+2h, active_period=1m30, inactive_period=1)";
+        let err = parse_command_with(input, &ctx).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "active_period", .. }));
+        assert_eq!(&input[err.span()], "1m30");
+    }
+
+    #[test]
+    fn test_timestamps_with_time_zones() {
+        let ctx = ParseContext {
+            timezone: Some(chrono_tz::Europe::Budapest),
+            ..Default::default()
+        };
+        let cmd = parse_command_with(
+            "set_update_interval(start_time=2026-10-23T15:30, end_time=2026-10-23T18:00[UTC], active_period=60, inactive_period=300)",
+            &ctx,
+        )
+        .unwrap();
+        assert!(cmd.to_string().contains("start_time=2026-10-23T13:30:00Z, end_time=2026-10-23T18:00:00Z"));
+
+        let input = "set_update_interval(start_time=2026-10-23T15:30[Europe/Budpest], end_time=now, active_period=1, inactive_period=1)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::UnknownTimeZone { suggestion: Some("Europe/Budapest"), .. }));
+        assert_eq!(&input[err.span()], "Europe/Budpest");
+
+        let input = "set_update_interval(start_time=2026-10-23T15:30, end_time=now, active_period=1, inactive_period=1)";
+        let err = parse_command(input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { name: "start_time", .. }));
+        assert!(err.to_string().contains("has no UTC offset"));
+    }
+
+    #[test]
+    fn test_parse_registry_commands() {
+        let registry = Registry::with_extra(
+            r#"
+[[command]]
+name = "set_radio_power"
+aliases = ["radio-power"]
+target = "required"
+params = [
+    { name = "dbm", type = "integer", max = 22 },
+    { name = "mode", type = "choice", values = ["LOW", "HIGH"], required = false },
+    { name = "until", type = "timestamp", required = false },
+    { name = "banner", type = "serial", required = false },
+]
+"#,
+        )
+        .unwrap();
+        let ctx = ParseContext {
+            now: Some(DateTime::parse_from_rfc3339("2026-10-23T12:00:00Z").unwrap().with_timezone(&Utc)),
+            registry: Some(Box::leak(Box::new(registry))),
+            ..Default::default()
+        };
+        let parse = |input: &str| parse_command_with(input, &ctx);
+
+        let cmd = parse("Radio-Power(node=21, mode=high, dbm=14, until=now+1h)").unwrap();
+        assert_eq!(
+            cmd,
+            Command::Custom {
+                name: "set_radio_power",
+                node_id: Some(21.into()),
+                params: vec![
+                    ("dbm", ArgValue::Integer(14)),
+                    ("mode", ArgValue::Text("HIGH".to_string())),
+                    ("until", ArgValue::Timestamp(ctx.now() + chrono::TimeDelta::hours(1))),
+                ],
+            }
+        );
+        assert_eq!(cmd.to_string(), r#"set_radio_power(node_id=21, dbm=14, mode="HIGH", until=2026-10-23T13:00:00Z)"#);
+        assert_eq!(parse(&cmd.to_string()).unwrap(), cmd);
+        assert_eq!(
+            cmd.to_json().unwrap(),
+            serde_json::json!({"command": "set_radio_power", "parameters": {"node id": 21, "dbm": 14, "mode": "HIGH", "until": "2026-10-23T13:00:00+00:00"}})
+        );
+        assert_eq!(parse("set_radio_power(node_id=21,22, dbm=1)").unwrap().expand(&[]).unwrap().len(), 2);
+
+        let input = "set_radio_power(node_id=21, dbm=30)";
+        let err = parse(input).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid dbm: must be at most 22");
+        assert_eq!(&input[err.span()], "30");
+        assert_eq!(parse("set_radio_power(node_id=21, dbm=1, mode=max)").unwrap_err().to_string(), "Invalid mode: must be LOW or HIGH");
+        assert!(matches!(parse("set_radio_power(dbm=1)").unwrap_err(), ParseError::RequiredParameter { name: "node_id", .. }));
+        assert!(matches!(parse("set_radio_power").unwrap_err(), ParseError::MissingArguments { command: "set_radio_power", .. }));
+        assert!(matches!(parse(r#"set_radio_power(node_id=21, dbm=1, banner="a\nb")"#).unwrap_err(), ParseError::InvalidValue { name: "banner", .. }));
+        assert!(matches!(
+            parse("set_radio_power(node_id=21, dbm=1, power=3)").unwrap_err(),
+            ParseError::UnknownParameter { command: "set_radio_power", .. }
+        ));
+        // Commands without a `positional` list take named arguments only
+        assert_eq!(parse("set_radio_power(21, dbm=1)").unwrap_err().to_string(), "set_radio_power takes no positional arguments");
+
+        // Only the context's registry knows the command
+        assert!(matches!(parse_command("set_radio_power(node_id=21, dbm=1)").unwrap_err(), ParseError::UnknownCommand { .. }));
+    }
+
+    #[test]
+    fn test_parse_help() {
+        assert_eq!(parse_command("help").unwrap(), Command::Help { name: None });
+        assert_eq!(
+            parse_command(" HELP set_log_level").unwrap(),
+            Command::Help {
+                name: Some("set_log_level".to_string())
+            }
+        );
+        assert!(parse_command("help a b").is_err());
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        let cmd = parse_command(r#"raw({"command": "set_radio_power", "parameters": {"node id": 21, "dbm": 14}})"#).unwrap();
+        let payload = serde_json::json!({"command": "set_radio_power", "parameters": {"node id": 21, "dbm": 14}});
+        assert_eq!(cmd, Command::Raw { payload: payload.clone() });
+        assert_eq!(cmd.to_json().unwrap(), payload);
+        assert_eq!(cmd.nodes(), None);
+        assert_eq!(parse_command(&cmd.to_string()).unwrap(), cmd);
+        assert!(matches!(parse_command(r#" RAW ( {"command": "x", "parameters": {}} ) "#).unwrap(), Command::Raw { .. }));
+
+        let input = r#"raw({"command": "x", "parameters": {},})"#;
+        let err = parse_command(input).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid raw payload: trailing comma");
+        assert_eq!(&input[err.span()], "}");
+        let input = r#"raw( {"command": "x"} )"#;
+        let err = parse_command(input).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid raw payload: missing field `parameters`");
+        assert_eq!(&input[err.span()], r#"{"command": "x"}"#);
+        // Positions inside non-ASCII text are widened to the whole character
+        for (input, at) in [("raw(é)", "é"), (r#"raw({"command": é})"#, "é"), (r#"raw({"command":"x","parameters":{}}é)"#, "é")] {
+            let err = parse_command(input).unwrap_err();
+            assert!(matches!(err, ParseError::InvalidPayload { .. }), "{}: {:?}", input, err);
+            assert_eq!(&input[err.span()], at, "{}", input);
+            assert!(err.render(input).ends_with('^'));
+        }
+
+        assert!(matches!(parse_command("raw").unwrap_err(), ParseError::MissingArguments { command: "raw", .. }));
+        assert!(matches!(parse_command("raw {}").unwrap_err(), ParseError::UnexpectedToken { expected: "'('", .. }));
+        assert!(matches!(parse_command(r#"raw({"command": "x""#).unwrap_err(), ParseError::MissingClosingParen { .. }));
+        // Only the bare keyword is raw
+        assert!(matches!(parse_command("rawx()").unwrap_err(), ParseError::UnknownCommand { .. }));
+    }
+
+    #[test]
+    fn test_command_aliases() {
+        let cmd = parse_command(r#"COMMAND(node_id=21, command="/LT")"#).unwrap();
+        assert_eq!(cmd.to_string(), r#"run_command(node_id=21, command="/LT")"#);
+        assert_eq!(deprecation_warning(r#"command(command="/LT")"#, &ParseContext::default()), None);
+
+        let input = "  set_filter(log_filter=x)";
+        let cmd = parse_command(input).unwrap();
+        assert!(matches!(cmd, Command::SetLogFilter { .. }));
+        let warning = deprecation_warning(input, &ParseContext::default()).unwrap();
+        assert_eq!(&input[warning.span.clone()], "set_filter");
+        assert_eq!(warning.to_string(), "set_filter is deprecated, use set_log_filter instead");
+
+        // Errors name the command the alias stands for
+        assert!(matches!(
+            parse_command("set_filter()").unwrap_err(),
+            ParseError::MissingParameter { name: "log_filter", .. }
+        ));
+        assert!(matches!(
+            parse_command("set_fliter(log_filter=x)").unwrap_err(),
+            ParseError::UnknownCommand { suggestion: Some("set_filter"), .. }
+        ));
+    }
+
+    /// A command shown in the user documentation.
+    struct DocExample {
+        file: &'static str,
+        line: usize,
+        input: String,
+        /// The rendered parse error printed after the example, if it demonstrates one
+        error: Option<String>,
+    }
+
+    /// Collects the commands from code blocks in the user documentation: `--command "..."`
+    /// arguments, interactive `> ...` lines and lines that start with a command name.
+    /// Examples using shell variables or `<placeholder>` values (as in `help` output) are skipped.
+    fn doc_examples(registry: &Registry) -> Vec<DocExample> {
+        let docs = [
+            ("README.md", include_str!("../README.md")),
+            ("EXAMPLES.md", include_str!("../EXAMPLES.md")),
+            ("PROJECT_SUMMARY.md", include_str!("../PROJECT_SUMMARY.md")),
+            ("examples.sh", include_str!("../examples.sh")),
+        ];
+        let names: Vec<&str> = registry.names().chain(registry::KEYWORDS.iter().copied()).collect();
+
+        let mut examples = Vec::new();
+        for (file, text) in docs {
+            let lines: Vec<&str> = text.lines().collect();
+            let mut in_block = !file.ends_with(".md");
+            for (idx, line) in lines.iter().enumerate() {
+                let line = line.trim();
+                if line.starts_with("```") {
+                    in_block = !in_block;
+                    continue;
+                }
+                if !in_block {
+                    continue;
+                }
+
+                let line = line.strip_prefix("> ").unwrap_or(line);
+                let input = if let Some(pos) = line.find("--command \"") {
+                    let end = line.rfind('"').unwrap();
+                    line[pos + 11..end].replace("\\\"", "\"")
+                } else {
+                    let first = line.split(|c: char| c == '(' || c.is_whitespace()).next().unwrap_or_default();
+                    let rest = &line[first.len()..];
+                    let is_command = rest.starts_with('(') || rest.is_empty() || ["nodes", "usb_commands", "help"].iter().any(|word| first.eq_ignore_ascii_case(word));
+                    if !is_command || !names.iter().any(|name| name.eq_ignore_ascii_case(first)) {
+                        continue;
+                    }
+                    line.to_string()
+                };
+                if input.contains('$') || input.contains("=<") {
+                    continue;
+                }
+
+                let error = lines
+                    .get(idx + 1)
+                    .filter(|next| next.starts_with("Parse error:"))
+                    .map(|_| lines[idx + 1..idx + 4].join("\n"));
+                examples.push(DocExample {
+                    file,
+                    line: idx + 1,
+                    input,
+                    error,
+                });
+            }
+        }
+        examples
+    }
+
+    #[test]
+    fn test_documentation_examples_parse() {
+        // The inventory from the README's example config
+        let inventory = vec![NodeInfo {
+            id: 21,
+            name: Some("roof-east".to_string()),
+            location: Some("Roof, east mast".to_string()),
+            tags: vec!["rooftop".to_string(), "outdoor".to_string()],
+            probe_hostname: Some("probe-21.local".to_string()),
+        }];
+        // The commands file from the README's Custom Commands section
+        let registry = Registry::with_extra(
+            r#"
+[[command]]
+name = "set_radio_power"
+aliases = ["radio-power"]
+target = "required"
+params = [
+    { name = "dbm", type = "integer", max = 22 },
+    { name = "mode", type = "choice", values = ["LOW", "HIGH"], required = false },
+]
+positional = ["node_id", "dbm"]
+"#,
+        )
+        .unwrap();
+        let ctx = ParseContext {
+            inventory: &inventory,
+            registry: Some(Box::leak(Box::new(registry))),
+            ..Default::default()
+        };
+
+        let examples = doc_examples(ctx.registry());
+        assert!(examples.len() > 50, "only {} examples found", examples.len());
+        let mut failures = Vec::new();
+        for example in examples {
+            for statement in crate::script::split_line(&example.input, example.line) {
+                let location = format!("{}:{}: {}", example.file, example.line, statement.text);
+                match (&example.error, parse_command_with(statement.text, &ctx)) {
+                    (None, Ok(_)) => {}
+                    (Some(expected), Err(e)) if e.render(statement.text) == *expected => {}
+                    (_, Err(e)) => failures.push(format!("{}\n{}", location, e.render(statement.text))),
+                    (Some(_), Ok(_)) => failures.push(format!("{}\nexpected a parse error", location)),
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+    }
+
+    #[test]
+    fn test_build_reports_mismatched_values() {
+        let registry = Registry::builtin();
+        let args = Args {
+            params: Vec::new(),
+            positional: Vec::new(),
+            span: 17..30,
+        };
+        let measurement = registry.find("start_measurement").unwrap();
+        let sequence = |number| vec![("sequence", ArgValue::Integer(number))];
+
+        assert!(matches!(build(measurement, &args, Some(21.into()), sequence(7)), Ok(Command::StartMeasurement { sequence: 7, .. })));
+        assert_eq!(
+            build(measurement, &args, Some(21.into()), sequence(1 << 32)),
+            Err(ParseError::InvalidValue {
+                name: "sequence",
+                reason: "must be at most 4294967295",
+                span: 17..30,
+            })
+        );
+        assert_eq!(
+            build(measurement, &args, None, sequence(7)),
+            Err(ParseError::RequiredParameter {
+                command: "start_measurement",
+                name: "node_id",
+                span: 17..30,
+            })
+        );
+        assert_eq!(
+            build(registry.find("set_log_level").unwrap(), &args, None, sequence(7)),
+            Err(ParseError::InvalidDefinition {
+                command: "set_log_level",
+                span: 17..30,
+            })
+        );
+    }
 }
@@ -0,0 +1,218 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fmt;
+
+use crate::iso8601;
+
+/// What a log filter would have kept of a collector log file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preview {
+    pub total: usize,
+    pub kept: usize,
+    pub total_bytes: usize,
+    pub kept_bytes: usize,
+    /// Lines without a leading timestamp, left out of the counts
+    pub skipped: usize,
+    pub kept_samples: Vec<String>,
+    pub dropped_samples: Vec<String>,
+    /// Time between the first and the last entry
+    pub duration: Option<TimeDelta>,
+}
+
+/// Splits a collector log line (`<timestamp>:<message>`) into its timestamp and message.
+///
+/// Timestamps contain colons themselves, so the separator is the last `:` (or tab) whose
+/// prefix is a whole ISO 8601 timestamp.
+pub fn split_entry(line: &str) -> Option<(DateTime<Utc>, &str)> {
+    line.match_indices([':', '\t'])
+        .filter_map(|(idx, _)| {
+            let timestamp = iso8601::parse(&line[..idx]).ok()?;
+            // The collector writes UTC; treat a missing offset the same way
+            let time = match timestamp.offset {
+                Some(offset) => timestamp.local.and_local_timezone(offset).single()?.with_timezone(&Utc),
+                None => timestamp.local.and_utc(),
+            };
+            Some((time, &line[idx + 1..]))
+        })
+        .next_back()
+}
+
+/// Applies `filter` the way the probe does (a case-sensitive substring match on the message,
+/// where an empty filter keeps everything) to every entry of `log`. Up to `samples` kept and
+/// dropped lines are picked evenly across the file.
+pub fn preview(log: &str, filter: &str, samples: usize) -> Preview {
+    let mut kept = Vec::new();
+    let mut dropped = Vec::new();
+    let mut skipped = 0;
+    let (mut first, mut last) = (None, None);
+
+    for line in log.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((time, message)) = split_entry(line) else {
+            skipped += 1;
+            continue;
+        };
+        first = first.or(Some(time));
+        last = Some(time);
+        if message.contains(filter) {
+            kept.push(line);
+        } else {
+            dropped.push(line);
+        }
+    }
+
+    // Every line also carries its newline on the wire
+    let bytes = |lines: &[&str]| lines.iter().map(|line| line.len() + 1).sum::<usize>();
+    let kept_bytes = bytes(&kept);
+    Preview {
+        total: kept.len() + dropped.len(),
+        kept: kept.len(),
+        total_bytes: kept_bytes + bytes(&dropped),
+        kept_bytes,
+        skipped,
+        kept_samples: spread(&kept, samples),
+        dropped_samples: spread(&dropped, samples),
+        duration: first.zip(last).map(|(first, last)| last - first),
+    }
+}
+
+/// Picks up to `count` lines spaced evenly across `lines`.
+fn spread(lines: &[&str], count: usize) -> Vec<String> {
+    if lines.len() <= count {
+        return lines.iter().map(|line| line.to_string()).collect();
+    }
+    (0..count).map(|i| lines[i * lines.len() / count].to_string()).collect()
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", value.round())
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+impl fmt::Display for Preview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Lines:    {} total, {} kept ({:.1}%), {} dropped",
+            self.total,
+            self.kept,
+            percent(self.kept, self.total),
+            self.total - self.kept
+        )?;
+        write!(
+            f,
+            "Volume:   {} -> {} ({:.1}% less)",
+            format_bytes(self.total_bytes as f64),
+            format_bytes(self.kept_bytes as f64),
+            100.0 - percent(self.kept_bytes, self.total_bytes).min(100.0)
+        )?;
+        // A per-day figure from a few seconds of log would be noise
+        if let Some(duration) = self.duration.filter(|duration| duration.num_seconds() >= 60) {
+            let per_day = 86_400.0 / duration.num_seconds() as f64;
+            write!(
+                f,
+                "\nPer day:  {} -> {} (over {} of log)",
+                format_bytes(self.total_bytes as f64 * per_day),
+                format_bytes(self.kept_bytes as f64 * per_day),
+                crate::time::format_duration(duration.num_seconds() as u64)
+            )?;
+        }
+        for (title, samples) in [("Kept", &self.kept_samples), ("Dropped", &self.dropped_samples)] {
+            if !samples.is_empty() {
+                write!(f, "\n{}:", title)?;
+                for line in samples {
+                    write!(f, "\n  {}", line)?;
+                }
+            }
+        }
+        if self.skipped > 0 {
+            write!(f, "\nSkipped:  {} lines without a timestamp", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+2025-10-23T18:00:00Z:[INFO] boot complete
+2025-10-23T18:00:05Z:[ERROR] radio: timeout
+2025-10-23T18:30:00Z:[INFO] rx 12 bytes
+
+not a log line
+2025-10-23T19:00:00.250Z:[WARN] low battery
+2025-10-23T20:00:00Z:[ERROR] radio: crc mismatch
+";
+
+    #[test]
+    fn test_split_entry() {
+        let (time, message) = split_entry("2025-10-23T18:00:05Z:[ERROR] radio: timeout").unwrap();
+        assert_eq!(time.to_rfc3339(), "2025-10-23T18:00:05+00:00");
+        assert_eq!(message, "[ERROR] radio: timeout");
+
+        assert_eq!(split_entry("2025-10-23T18:00:05.5Z\t12:30 ok").unwrap().1, "12:30 ok");
+        assert_eq!(split_entry("2025-10-23T18:00:05+01:00:msg").unwrap().0.to_rfc3339(), "2025-10-23T17:00:05+00:00");
+        assert_eq!(split_entry("2025-10-23T18:00:05Z:").unwrap().1, "");
+        assert_eq!(split_entry("[INFO] no timestamp: here"), None);
+    }
+
+    #[test]
+    fn test_preview_counts() {
+        let preview = preview(LOG, "[ERROR]", 5);
+        assert_eq!((preview.total, preview.kept, preview.skipped), (5, 2, 1));
+        assert_eq!(preview.kept_samples.len(), 2);
+        assert_eq!(preview.dropped_samples[0], "2025-10-23T18:00:00Z:[INFO] boot complete");
+        assert_eq!(preview.duration, Some(TimeDelta::hours(2)));
+        assert_eq!(preview.total_bytes, LOG.len() - "\nnot a log line\n".len());
+
+        // The match is case sensitive and only looks at the message
+        assert_eq!(preview_kept(LOG, "[error]"), 0);
+        assert_eq!(preview_kept(LOG, "2025"), 0);
+        assert_eq!(preview_kept(LOG, ""), 5);
+    }
+
+    fn preview_kept(log: &str, filter: &str) -> usize {
+        preview(log, filter, 0).kept
+    }
+
+    #[test]
+    fn test_samples_are_spread_out() {
+        let lines = ["a", "b", "c", "d", "e", "f"];
+        assert_eq!(spread(&lines, 3), vec!["a", "c", "e"]);
+        assert_eq!(spread(&lines[..2], 3), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_report() {
+        let report = preview(LOG, "radio", 1).to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Lines:    5 total, 2 kept (40.0%), 3 dropped");
+        assert_eq!(lines[1], "Volume:   219 B -> 93 B (57.5% less)");
+        assert_eq!(lines[2], "Per day:  2.6 KiB -> 1.1 KiB (over 2h of log)");
+        assert_eq!(&lines[3..], [
+            "Kept:",
+            "  2025-10-23T18:00:05Z:[ERROR] radio: timeout",
+            "Dropped:",
+            "  2025-10-23T18:00:00Z:[INFO] boot complete",
+            "Skipped:  1 lines without a timestamp",
+        ]);
+    }
+}
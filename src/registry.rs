@@ -0,0 +1,483 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Commands with their own syntax (`nodes show <name>`), handled by the parser directly.
+/// `(usage, description)` pairs, shown by `help`.
+pub const KEYWORD_COMMANDS: &[(&str, &str)] = &[
+    ("nodes [show <name>]", "List the node inventory, or show one node"),
+    ("usb_commands [prefix]", "List the node USB commands, or those starting with prefix"),
+    ("help [command]", "List the commands, or describe one"),
+    ("raw(<json>)", "Send a hub request body as written, without local validation"),
+    ("quit", "Leave interactive mode (also exit and bye)"),
+];
+
+/// Names the parser handles before looking in the registry, so commands can't use them.
+pub const KEYWORDS: &[&str] = &["nodes", "usb_commands", "help", "raw", "quit", "exit", "bye"];
+
+/// `(name, target, (parameter name, type) pairs)` of a built-in command.
+pub type TypedCommand = (&'static str, Target, &'static [(&'static str, ParamType)]);
+
+/// Built-in commands that `parser::build` turns into typed `Command` variants, with the
+/// `target` and the parameters, in order, that the variant is built from.
+pub const TYPED_COMMANDS: &[TypedCommand] = &[
+    (
+        "set_update_interval",
+        Target::Optional,
+        &[
+            ("start_time", ParamType::Timestamp),
+            ("end_time", ParamType::Timestamp),
+            ("active_period", ParamType::Duration),
+            ("inactive_period", ParamType::Duration),
+        ],
+    ),
+    ("set_log_level", Target::Optional, &[("log_level", ParamType::Choice)]),
+    ("set_log_filter", Target::Optional, &[("log_filter", ParamType::Serial)]),
+    ("run_command", Target::Optional, &[("command", ParamType::Serial)]),
+    ("update_node", Target::Optional, &[]),
+    ("update_probe", Target::Optional, &[]),
+    ("reboot_probe", Target::Optional, &[]),
+    ("start_measurement", Target::Required, &[("sequence", ParamType::Integer)]),
+    ("preview_filter", Target::None, &[("log_filter", ParamType::Serial), ("file", ParamType::String)]),
+];
+
+/// Whether a command takes a `node_id` parameter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// No `node_id`; the command is not aimed at nodes
+    None,
+    /// All nodes unless `node_id` is given
+    #[default]
+    Optional,
+    /// `node_id` must be given
+    Required,
+}
+
+/// How a parameter value is written and sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    /// Unquoted non-negative integer, sent as a number
+    Integer,
+    /// Any text, sent as a string
+    String,
+    /// Text written to the node's serial console, checked like `run_command` values
+    Serial,
+    /// Absolute or relative timestamp, sent in RFC 3339 format
+    Timestamp,
+    /// Seconds or a duration such as `1m30s`, sent as a number of seconds
+    Duration,
+    /// One of `values`, matched ignoring case and sent as listed
+    Choice,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ParamSpec {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    #[serde(default = "default_required")]
+    pub required: bool,
+    #[serde(default)]
+    pub description: String,
+    /// Allowed values of a `choice` parameter
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// Largest value of an `integer` parameter
+    pub max: Option<u64>,
+    /// Earlier `timestamp` parameter that `+<duration>` values are relative to
+    pub relative_to: Option<String>,
+    /// Why a value outside `values` or above `max` is rejected, filled in by `Registry::check`
+    #[serde(skip)]
+    pub reason: String,
+}
+
+fn default_required() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CommandSpec {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Aliases that print a warning pointing to `name`
+    #[serde(default)]
+    pub deprecated_aliases: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub target: Target,
+    #[serde(default)]
+    pub params: Vec<ParamSpec>,
+    /// Keys that may be given without `key=`, in order, e.g. `["node_id", "log_level"]`
+    #[serde(default)]
+    pub positional: Vec<String>,
+    /// Handled by the CLI rather than sent to the hub; only built-in commands can be local
+    #[serde(default)]
+    pub local: bool,
+}
+
+impl CommandSpec {
+    /// Whether the command can be written without parentheses, like `update_node`.
+    pub fn takes_no_arguments(&self) -> bool {
+        self.target != Target::Required && self.params.iter().all(|param| !param.required)
+    }
+
+    pub fn param(&self, name: &str) -> Option<&ParamSpec> {
+        self.params.iter().find(|param| param.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Parameter names accepted in the argument list, `node_id` included.
+    pub fn keys(&self) -> Vec<&str> {
+        let node = (self.target != Target::None).then_some("node_id");
+        node.into_iter().chain(self.params.iter().map(|param| param.name.as_str())).collect()
+    }
+
+    /// One-line syntax, e.g. `set_log_level([node_id=<nodes>], log_level=TRACE|DEBUG|INFO|WARN|ERROR)`.
+    pub fn usage(&self) -> String {
+        let node = match self.target {
+            Target::None => None,
+            Target::Optional => Some("[node_id=<nodes>]".to_string()),
+            Target::Required => Some("node_id=<nodes>".to_string()),
+        };
+        let params = self.params.iter().map(|param| {
+            let placeholder = match param.kind {
+                ParamType::Integer => "<integer>".to_string(),
+                ParamType::String | ParamType::Serial => "<text>".to_string(),
+                ParamType::Timestamp => "<timestamp>".to_string(),
+                ParamType::Duration => "<duration>".to_string(),
+                ParamType::Choice => param.values.join("|"),
+            };
+            if param.required {
+                format!("{}={}", param.name, placeholder)
+            } else {
+                format!("[{}={}]", param.name, placeholder)
+            }
+        });
+        format!("{}({})", self.name, node.into_iter().chain(params).collect::<Vec<_>>().join(", "))
+    }
+
+    /// Usage, description, aliases and parameters, for `help <command>`.
+    pub fn format_help(&self) -> String {
+        let mut lines = vec![self.usage()];
+        if !self.description.is_empty() {
+            lines.push(format!("  {}", self.description));
+        }
+        if self.local {
+            lines.push("  Runs locally; nothing is sent to the hub".to_string());
+        }
+        if !self.aliases.is_empty() {
+            lines.push(format!("  Aliases: {}", self.aliases.join(", ")));
+        }
+        if !self.deprecated_aliases.is_empty() {
+            lines.push(format!("  Deprecated aliases: {}", self.deprecated_aliases.join(", ")));
+        }
+        if !self.positional.is_empty() {
+            lines.push(format!("  Positional: {}", self.positional.join(", ")));
+        }
+        match self.target {
+            Target::None => {}
+            Target::Optional => lines.push(format!("  {:<16} Target nodes; all nodes if absent", "node_id")),
+            Target::Required => lines.push(format!("  {:<16} Target nodes", "node_id")),
+        }
+        for param in &self.params {
+            let optional = (!param.required).then_some("(optional)");
+            let text: Vec<&str> = std::iter::once(param.description.as_str()).chain(optional).filter(|part| !part.is_empty()).collect();
+            lines.push(format!("  {:<16} {}", param.name, text.join(" ")).trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+/// The commands the parser knows: the built-in ones from `commands.toml`, plus any from the
+/// `commands-file` named in the config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registry {
+    #[serde(rename = "command", default)]
+    pub commands: Vec<CommandSpec>,
+}
+
+impl Registry {
+    pub fn builtin() -> &'static Registry {
+        static BUILTIN: OnceLock<Registry> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            let registry: Registry = toml::from_str(include_str!("commands.toml")).expect("commands.toml is valid TOML");
+            let builtin = registry.commands.len();
+            registry.check(builtin).expect("commands.toml is a valid registry")
+        })
+    }
+
+    /// The built-in commands plus the ones in `content`.
+    pub fn with_extra(content: &str) -> Result<Registry> {
+        let extra: Registry = toml::from_str(content).context("Failed to parse command file")?;
+        if let Some(spec) = extra.commands.iter().find(|spec| spec.local) {
+            bail!("{} cannot be local; only built-in commands run locally", spec.name);
+        }
+        let mut registry = Self::builtin().clone();
+        let builtin = registry.commands.len();
+        registry.commands.extend(extra.commands);
+        registry.check(builtin)
+    }
+
+    /// Loads a command file. The registry is used for the rest of the run and kept in a
+    /// static, like the built-in one, to give parsed commands `'static` names.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<&'static Registry> {
+        static LOADED: OnceLock<Registry> = OnceLock::new();
+        let content = std::fs::read_to_string(path.as_ref()).context("Failed to read command file")?;
+        if LOADED.set(Self::with_extra(&content)?).is_err() {
+            bail!("A command file has already been loaded");
+        }
+        LOADED.get().context("A command file has already been loaded")
+    }
+
+    /// Rejects clashing or malformed entries and fills in `ParamSpec::reason`. The first
+    /// `builtin` entries are from `commands.toml`; the rest come from a command file and may
+    /// not reuse a built-in name.
+    fn check(mut self, builtin: usize) -> Result<Registry> {
+        // Each name with whether a built-in command or keyword owns it
+        let mut names: Vec<(&str, bool)> = KEYWORDS.iter().map(|name| (*name, true)).collect();
+        for (idx, spec) in self.commands.iter().enumerate() {
+            for name in std::iter::once(&spec.name).chain(&spec.aliases).chain(&spec.deprecated_aliases) {
+                if !is_identifier(name) {
+                    bail!("Command name {:?} must be lower case letters, digits, '_' and '-', starting with a letter", name);
+                }
+                match names.iter().find(|(known, _)| known == name) {
+                    Some((_, true)) if idx >= builtin => bail!("Command name {} is already a built-in command; command files can only add new commands", name),
+                    Some(_) => bail!("Command name {} is defined more than once", name),
+                    None => names.push((name, idx < builtin)),
+                }
+            }
+
+            for (idx, param) in spec.params.iter().enumerate() {
+                let context = || format!("parameter {} of {}", param.name, spec.name);
+                if !is_identifier(&param.name) {
+                    bail!("Invalid name for {}: must be lower case letters, digits, '_' and '-', starting with a letter", context());
+                }
+                if param.name == "node_id" || param.name == "node" {
+                    bail!("Invalid name for {}: node_id is set with `target`", context());
+                }
+                if spec.params[..idx].iter().any(|other| other.name == param.name) {
+                    bail!("Duplicate {}", context());
+                }
+                if (param.kind == ParamType::Choice) == param.values.is_empty() {
+                    bail!("Invalid {}: `values` is required for, and only allowed on, choice parameters", context());
+                }
+                if param.max.is_some() && param.kind != ParamType::Integer {
+                    bail!("Invalid {}: `max` is only allowed on integer parameters", context());
+                }
+                if let Some(start) = &param.relative_to {
+                    let earlier = spec.params[..idx].iter().any(|other| &other.name == start && other.kind == ParamType::Timestamp);
+                    if param.kind != ParamType::Timestamp || !earlier {
+                        bail!("Invalid {}: `relative-to` must name an earlier timestamp parameter", context());
+                    }
+                }
+            }
+
+            let keys = spec.keys();
+            for (idx, key) in spec.positional.iter().enumerate() {
+                if !keys.contains(&key.as_str()) {
+                    bail!("Invalid positional parameter {} of {}: not a parameter of the command", key, spec.name);
+                }
+                if spec.positional[..idx].contains(key) {
+                    bail!("Duplicate positional parameter {} of {}", key, spec.name);
+                }
+            }
+        }
+
+        for &(name, target, params) in TYPED_COMMANDS {
+            let Some(spec) = self.commands[..builtin].iter().find(|spec| spec.name == name) else {
+                bail!("Built-in command {} is missing from commands.toml", name);
+            };
+            let found: Vec<(&str, ParamType)> = spec.params.iter().map(|param| (param.name.as_str(), param.kind)).collect();
+            if spec.target != target || found != params || spec.params.iter().any(|param| !param.required) {
+                let expected: Vec<String> = params.iter().map(|(param, kind)| format!("{} ({:?})", param, kind)).collect();
+                bail!(
+                    "Built-in command {} must have target {:?} and the required parameters [{}], in this order, as parser::build expects",
+                    name,
+                    target,
+                    expected.join(", ")
+                );
+            }
+        }
+
+        for param in self.commands.iter_mut().flat_map(|spec| spec.params.iter_mut()) {
+            param.reason = match (&param.kind, param.max) {
+                (ParamType::Choice, _) => format!("must be {}", list_or(&param.values)),
+                (ParamType::Integer, Some(max)) => format!("must be at most {}", max),
+                _ => String::new(),
+            };
+        }
+        Ok(self)
+    }
+
+    /// Looks up a command by name or alias, ignoring case.
+    pub fn find(&self, name: &str) -> Option<&CommandSpec> {
+        self.commands.iter().find(|spec| {
+            std::iter::once(&spec.name)
+                .chain(&spec.aliases)
+                .chain(&spec.deprecated_aliases)
+                .any(|known| known.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// If `name` is a deprecated alias, the alias as registered and the command it stands for.
+    pub fn deprecated_alias(&self, name: &str) -> Option<(&str, &str)> {
+        self.commands.iter().find_map(|spec| {
+            spec.deprecated_aliases
+                .iter()
+                .find(|alias| alias.eq_ignore_ascii_case(name))
+                .map(|alias| (alias.as_str(), spec.name.as_str()))
+        })
+    }
+
+    /// Every name and alias, for suggestions.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.commands
+            .iter()
+            .flat_map(|spec| std::iter::once(&spec.name).chain(&spec.aliases).chain(&spec.deprecated_aliases))
+            .map(String::as_str)
+    }
+
+    /// Help for a registry or keyword command, for `help <command>`.
+    pub fn describe(&self, name: &str) -> Option<String> {
+        if let Some(spec) = self.find(name) {
+            return Some(spec.format_help());
+        }
+        KEYWORD_COMMANDS
+            .iter()
+            .find(|(usage, _)| usage.split([' ', '(']).next().is_some_and(|word| word.eq_ignore_ascii_case(name)))
+            .map(|(usage, description)| format!("{}\n  {}", usage, description))
+    }
+
+    /// The command list shown by `help`.
+    pub fn format_help(&self) -> String {
+        let width = self.commands.iter().map(|spec| spec.name.len()).chain(KEYWORD_COMMANDS.iter().map(|(usage, _)| usage.len())).max().unwrap_or(0);
+        let commands = self.commands.iter().map(|spec| format!("{:<width$}  {}", spec.name, spec.description));
+        let keywords = KEYWORD_COMMANDS.iter().map(|(usage, description)| format!("{:<width$}  {}", usage, description));
+        let mut lines: Vec<String> = commands.chain(keywords).collect();
+        lines.push("Type 'help <command>' for its parameters.".to_string());
+        lines.join("\n")
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// `A, B, or C`
+fn list_or(values: &[String]) -> String {
+    match values {
+        [] => String::new(),
+        [only] => only.clone(),
+        [first, second] => format!("{} or {}", first, second),
+        [rest @ .., last] => format!("{}, or {}", rest.join(", "), last),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTRA: &str = r#"
+[[command]]
+name = "set_radio_power"
+aliases = ["radio-power"]
+description = "Set the LoRa transmit power"
+target = "required"
+params = [
+    { name = "dbm", type = "integer", max = 22, description = "Transmit power in dBm" },
+    { name = "mode", type = "choice", values = ["LOW", "HIGH"], required = false },
+]
+"#;
+
+    #[test]
+    fn test_builtin_registry() {
+        let registry = Registry::builtin();
+        assert_eq!(registry.find("COMMAND").unwrap().name, "run_command");
+        assert_eq!(registry.find("set_filter").unwrap().name, "set_log_filter");
+        assert_eq!(registry.deprecated_alias("Set_Filter"), Some(("set_filter", "set_log_filter")));
+        assert_eq!(registry.deprecated_alias("command"), None);
+        assert!(registry.find("nodes").is_none());
+
+        let level = registry.find("set_log_level").unwrap().param("log_level").unwrap();
+        assert_eq!(level.reason, "must be TRACE, DEBUG, INFO, WARN, or ERROR");
+        assert!(registry.find("update_node").unwrap().takes_no_arguments());
+        assert!(!registry.find("start_measurement").unwrap().takes_no_arguments());
+        assert!(registry.find("set_log_level").unwrap().format_help().contains("\n  Positional: node_id, log_level\n"));
+    }
+
+    #[test]
+    fn test_extra_commands() {
+        let registry = Registry::with_extra(EXTRA).unwrap();
+        let spec = registry.find("radio-power").unwrap();
+        assert_eq!(spec.keys(), ["node_id", "dbm", "mode"]);
+        assert_eq!(spec.param("dbm").unwrap().reason, "must be at most 22");
+        assert_eq!(spec.usage(), "set_radio_power(node_id=<nodes>, dbm=<integer>, [mode=LOW|HIGH])");
+        assert_eq!(
+            spec.format_help(),
+            "set_radio_power(node_id=<nodes>, dbm=<integer>, [mode=LOW|HIGH])
+  Set the LoRa transmit power
+  Aliases: radio-power
+  node_id          Target nodes
+  dbm              Transmit power in dBm
+  mode             (optional)"
+        );
+        assert!(registry.format_help().contains("\nset_radio_power        Set the LoRa transmit power\n"));
+    }
+
+    #[test]
+    fn test_rejects_invalid_command_files() {
+        let err = |content: &str| format!("{:#}", Registry::with_extra(content).unwrap_err());
+
+        assert_eq!(err("[[command]]\nname = \"update_node\""), "Command name update_node is already a built-in command; command files can only add new commands");
+        assert_eq!(err("[[command]]\nname = \"x\"\naliases = [\"set_filter\"]"), "Command name set_filter is already a built-in command; command files can only add new commands");
+        assert_eq!(err("[[command]]\nname = \"x\"\naliases = [\"help\"]"), "Command name help is already a built-in command; command files can only add new commands");
+        assert_eq!(err("[[command]]\nname = \"x\"\n[[command]]\nname = \"y\"\naliases = [\"x\"]"), "Command name x is defined more than once");
+        assert!(err("[[command]]\nname = \"Bad Name\"").starts_with("Command name \"Bad Name\" must be"));
+        assert_eq!(err("[[command]]\nname = \"x\"\nlocal = true"), "x cannot be local; only built-in commands run locally");
+        assert!(err("[[command]]\nname = \"x\"\nparams = [{ name = \"node_id\", type = \"integer\" }]").contains("node_id is set with `target`"));
+        assert!(err("[[command]]\nname = \"x\"\nparams = [{ name = \"a\", type = \"choice\" }]").contains("`values` is required"));
+        assert!(err("[[command]]\nname = \"x\"\nparams = [{ name = \"a\", type = \"string\", max = 3 }]").contains("`max` is only allowed"));
+        assert!(err("[[command]]\nname = \"x\"\nparams = [{ name = \"a\", type = \"timestamp\", relative-to = \"b\" }]").contains("`relative-to` must name"));
+        assert!(err("[[command]]\nname = \"x\"\nparams = [{ name = \"a\", type = \"float\" }]").contains("unknown variant `float`"));
+        assert_eq!(err("[[command]]\nname = \"x\"\ntarget = \"none\"\npositional = [\"node_id\"]"), "Invalid positional parameter node_id of x: not a parameter of the command");
+        assert_eq!(err("[[command]]\nname = \"x\"\npositional = [\"node_id\", \"node_id\"]"), "Duplicate positional parameter node_id of x");
+        assert!(err("[[command]]\nname = \"x\"\nnode = 3").contains("unknown field `node`"));
+    }
+
+    #[test]
+    fn test_rejects_builtin_entries_build_cannot_use() {
+        let check = |edit: fn(&mut Registry)| {
+            let mut registry = Registry::builtin().clone();
+            edit(&mut registry);
+            let builtin = registry.commands.len();
+            registry.check(builtin).map(|_| ()).map_err(|e| e.to_string())
+        };
+
+        assert_eq!(check(|_| {}), Ok(()));
+        assert_eq!(
+            check(|registry| registry.commands.retain(|spec| spec.name != "reboot_probe")),
+            Err("Built-in command reboot_probe is missing from commands.toml".to_string())
+        );
+        assert_eq!(
+            check(|registry| registry.commands[3].params[0].kind = ParamType::String),
+            Err("Built-in command run_command must have target Optional and the required parameters [command (Serial)], in this order, as parser::build expects".to_string())
+        );
+        assert!(check(|registry| registry.commands[7].target = Target::Optional).is_err());
+        assert!(check(|registry| registry.commands[0].params[3].required = false).is_err());
+        // Renamed or reordered parameters would fill the wrong fields of the typed command
+        assert!(check(|registry| registry.commands[1].params[0].name = "level".to_string()).is_err());
+        assert!(check(|registry| registry.commands[0].params.swap(0, 1)).is_err());
+        assert!(check(|registry| {
+            registry.commands[1].params.pop();
+        })
+        .is_err());
+    }
+}
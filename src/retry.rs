@@ -0,0 +1,129 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// When and how often a failed request is sent again, from the `[retry]` table of the config.
+/// Which failures are worth retrying is decided by `ClientError::is_retryable`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RetryPolicy {
+    /// Attempts including the first one; 1 turns retries off
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for every further one
+    pub base_delay_ms: u64,
+    /// Fraction of each wait that is random (0 to 1), so stations don't retry in lockstep
+    pub jitter: f64,
+    /// No attempt is started later than this after the first one
+    pub deadline_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay_ms: 500,
+            jitter: 0.2,
+            deadline_secs: 30,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A single attempt, for `--no-retry`.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            bail!("retry.max-attempts must be at least 1");
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            bail!("retry.jitter must be between 0 and 1");
+        }
+        Ok(())
+    }
+
+    /// Exponential back-off after the `attempt`th attempt failed. `random` is in `[0, 1)` and
+    /// shortens the wait by up to `jitter` of it.
+    pub fn backoff(&self, attempt: u32, random: f64) -> Duration {
+        let full = self.base_delay_ms.saturating_mul(1 << attempt.saturating_sub(1).min(63));
+        Duration::from_millis((full as f64 * (1.0 - self.jitter * random)) as u64)
+    }
+
+    /// How long to wait before the next attempt, or `None` to give up. The wait is at least
+    /// the hub's `Retry-After`, and no attempt starts after the deadline.
+    pub fn next_delay(&self, attempt: u32, elapsed: Duration, retry_after: Option<Duration>, random: f64) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let delay = self.backoff(attempt, random).max(retry_after.unwrap_or_default());
+        // A huge Retry-After must not overflow the sum
+        elapsed
+            .checked_add(delay)
+            .is_some_and(|end| end <= Duration::from_secs(self.deadline_secs))
+            .then_some(delay)
+    }
+}
+
+/// Reads a `Retry-After` header: a number of seconds or an HTTP date.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means "now"
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_with_jitter() {
+        let policy = RetryPolicy::default();
+        let millis = |attempt, random| policy.backoff(attempt, random).as_millis();
+        assert_eq!([millis(1, 0.0), millis(2, 0.0), millis(3, 0.0)], [500, 1000, 2000]);
+        assert_eq!(millis(2, 0.5), 900);
+        assert_eq!(millis(2, 0.999), 800);
+        assert_eq!(millis(100, 0.0), u64::MAX as u128);
+    }
+
+    #[test]
+    fn test_next_delay_limits() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let secs = Duration::from_secs;
+        assert_eq!(policy.next_delay(1, secs(0), None, 0.0), Some(Duration::from_millis(500)));
+        assert_eq!(policy.next_delay(4, secs(0), None, 0.0), None);
+        // Retry-After only ever lengthens the wait
+        assert_eq!(policy.next_delay(1, secs(0), Some(secs(5)), 0.0), Some(secs(5)));
+        assert_eq!(policy.next_delay(3, secs(0), Some(secs(1)), 0.0), Some(secs(2)));
+        assert_eq!(policy.next_delay(1, secs(26), Some(secs(5)), 0.0), None);
+        assert_eq!(policy.next_delay(1, secs(29), None, 0.0), Some(Duration::from_millis(500)));
+        assert_eq!(policy.next_delay(1, secs(1), Some(secs(u64::MAX)), 0.0), None);
+        assert_eq!(policy.next_delay(1, Duration::from_millis(1), Some(secs(u64::MAX)), 0.0), None);
+        assert_eq!(RetryPolicy::none().next_delay(1, secs(0), None, 0.0), None);
+
+        assert!(RetryPolicy { max_attempts: 0, ..Default::default() }.validate().is_err());
+        assert!(RetryPolicy { jitter: 1.5, ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2026-10-17T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sat, 17 Oct 2026 12:00:30 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Sat, 17 Oct 2026 11:00:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-1", now), None);
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+use thiserror::Error;
+
+use crate::parser::Command;
+use crate::time::format_duration;
+
+/// Periods above this are more likely a unit mix-up than a real schedule.
+const LARGE_PERIOD: u64 = 86_400;
+
+/// Reasons an upload schedule is rejected before it is sent.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ScheduleError {
+    #[error("{name} must be positive")]
+    ZeroPeriod { name: &'static str },
+    #[error("end_time {end} is not after start_time {start}")]
+    Inverted { start: DateTime<Utc>, end: DateTime<Utc> },
+    #[error("the window ended at {end}, which is in the past")]
+    Past { end: DateTime<Utc> },
+}
+
+/// Schedules that are valid but probably not what was meant.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ScheduleWarning {
+    #[error("{name} is {}; probes will upload only that often", format_duration(*.seconds))]
+    LargePeriod { name: &'static str, seconds: u64 },
+    #[error("active_period ({}) is longer than the window ({}); probes may not upload during it", format_duration(*.active), format_duration(*.window))]
+    ActiveExceedsWindow { active: u64, window: u64 },
+}
+
+/// Checks the upload schedule of a `set_update_interval` command against `now`.
+/// Other commands have no schedule and always pass.
+pub fn validate(command: &Command, now: DateTime<Utc>) -> Result<Vec<ScheduleWarning>, ScheduleError> {
+    let Command::SetUpdateInterval {
+        start_time,
+        end_time,
+        active_period,
+        inactive_period,
+        ..
+    } = *command
+    else {
+        return Ok(Vec::new());
+    };
+
+    for (name, seconds) in [("active_period", active_period), ("inactive_period", inactive_period)] {
+        if seconds == 0 {
+            return Err(ScheduleError::ZeroPeriod { name });
+        }
+    }
+    if end_time <= start_time {
+        return Err(ScheduleError::Inverted {
+            start: start_time,
+            end: end_time,
+        });
+    }
+    if end_time <= now {
+        return Err(ScheduleError::Past { end: end_time });
+    }
+
+    let mut warnings = Vec::new();
+    for (name, seconds) in [("active_period", active_period), ("inactive_period", inactive_period)] {
+        if seconds > LARGE_PERIOD {
+            warnings.push(ScheduleWarning::LargePeriod { name, seconds });
+        }
+    }
+    let window = (end_time - start_time).num_seconds().unsigned_abs();
+    if active_period > window {
+        warnings.push(ScheduleWarning::ActiveExceedsWindow {
+            active: active_period,
+            window,
+        });
+    }
+    Ok(warnings)
+}
+
+/// Describes the window in `zone` (the system zone if `None`), e.g.
+/// `2026-10-23 15:30:00 CEST to 2026-10-23 18:00:00 CEST (2h30m)`.
+pub fn describe_window(start: DateTime<Utc>, end: DateTime<Utc>, zone: Option<Tz>) -> String {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S %Z";
+    let (start_text, end_text) = match zone {
+        Some(zone) => (start.with_timezone(&zone).format(FORMAT), end.with_timezone(&zone).format(FORMAT)),
+        None => (start.with_timezone(&Local).format(FORMAT), end.with_timezone(&Local).format(FORMAT)),
+    };
+    let length = (end - start).num_seconds().max(0) as u64;
+    format!("{} to {} ({})", start_text, end_text, format_duration(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    fn schedule(start: &str, end: &str, active_period: u64, inactive_period: u64) -> Command {
+        Command::SetUpdateInterval {
+            node_id: None,
+            start_time: at(start),
+            end_time: at(end),
+            active_period,
+            inactive_period,
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_schedules() {
+        let now = at("2026-10-23T12:00:00Z");
+        let check = |command| validate(&command, now);
+
+        assert_eq!(check(schedule("2026-10-23T13:00:00Z", "2026-10-23T15:00:00Z", 60, 300)), Ok(Vec::new()));
+        // A window that has started but not ended is fine
+        assert_eq!(check(schedule("2026-10-23T11:00:00Z", "2026-10-23T12:00:01Z", 60, 300)), Ok(Vec::new()));
+
+        assert_eq!(
+            check(schedule("2026-10-23T13:00:00Z", "2026-10-23T15:00:00Z", 0, 300)),
+            Err(ScheduleError::ZeroPeriod { name: "active_period" })
+        );
+        assert_eq!(
+            check(schedule("2026-10-23T13:00:00Z", "2026-10-23T15:00:00Z", 60, 0)),
+            Err(ScheduleError::ZeroPeriod { name: "inactive_period" })
+        );
+        assert!(matches!(check(schedule("2026-10-23T15:00:00Z", "2026-10-23T13:00:00Z", 60, 300)), Err(ScheduleError::Inverted { .. })));
+        assert!(matches!(check(schedule("2026-10-23T15:00:00Z", "2026-10-23T15:00:00Z", 60, 300)), Err(ScheduleError::Inverted { .. })));
+        assert_eq!(
+            check(schedule("2026-10-22T13:00:00Z", "2026-10-23T12:00:00Z", 60, 300)),
+            Err(ScheduleError::Past { end: now })
+        );
+
+        assert_eq!(validate(&Command::UpdateNode { node_id: None }, now), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_warns_on_large_periods() {
+        let now = at("2026-10-23T12:00:00Z");
+        let warnings = validate(&schedule("2026-10-23T13:00:00Z", "2026-10-23T14:00:00Z", 7_200, 172_800), now).unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                ScheduleWarning::LargePeriod { name: "inactive_period", seconds: 172_800 },
+                ScheduleWarning::ActiveExceedsWindow { active: 7_200, window: 3_600 },
+            ]
+        );
+        assert_eq!(warnings[0].to_string(), "inactive_period is 2d; probes will upload only that often");
+        assert_eq!(warnings[1].to_string(), "active_period (2h) is longer than the window (1h); probes may not upload during it");
+    }
+
+    #[test]
+    fn test_describe_window() {
+        let zone = Some(chrono_tz::Europe::Budapest);
+        assert_eq!(
+            describe_window(at("2026-10-23T13:30:00Z"), at("2026-10-23T16:00:00Z"), zone),
+            "2026-10-23 15:30:00 CEST to 2026-10-23 18:00:00 CEST (2h30m)"
+        );
+        // Across the change back to winter time
+        assert_eq!(
+            describe_window(at("2026-10-24T22:00:00Z"), at("2026-10-25T06:00:00Z"), zone),
+            "2026-10-25 00:00:00 CEST to 2026-10-25 07:00:00 CET (8h)"
+        );
+    }
+}
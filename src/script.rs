@@ -0,0 +1,111 @@
+/// One command within a line of input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statement<'a> {
+    /// 1-based line number within the source
+    pub line_number: usize,
+    /// The whole line, used when rendering diagnostics
+    pub line: &'a str,
+    /// Byte offset of `text` within `line`
+    pub start: usize,
+    pub text: &'a str,
+}
+
+/// Splits one line into `;`-separated commands, dropping a trailing `#` comment.
+///
+/// Separators and `#` inside quoted strings are part of the command. Blank commands are skipped.
+pub fn split_line(line: &str, line_number: usize) -> Vec<Statement<'_>> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut end = line.len();
+
+    let mut push = |start: usize, end: usize| {
+        let text = &line[start..end];
+        if !text.trim().is_empty() {
+            statements.push(Statement {
+                line_number,
+                line,
+                start,
+                text,
+            });
+        }
+    };
+
+    for (idx, ch) in line.char_indices() {
+        if in_quotes {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_quotes = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_quotes = true,
+            ';' => {
+                push(start, idx);
+                start = idx + 1;
+            }
+            '#' => {
+                end = idx;
+                break;
+            }
+            _ => {}
+        }
+    }
+    push(start, end);
+
+    statements
+}
+
+/// Splits a script into statements, one or more per non-empty line.
+pub fn statements(source: &str) -> Vec<Statement<'_>> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(idx, line)| split_line(line, idx + 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Vec<&str> {
+        split_line(line, 1).into_iter().map(|s| s.text).collect()
+    }
+
+    #[test]
+    fn test_split_on_semicolons() {
+        assert_eq!(
+            texts("update_node(node_id=21); reboot_probe(node_id=21) ;"),
+            vec!["update_node(node_id=21)", " reboot_probe(node_id=21) "]
+        );
+        let statements = split_line("a; b", 7);
+        assert_eq!(statements[1].start, 2);
+        assert_eq!(statements[1].line_number, 7);
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(texts("# just a comment"), Vec::<&str>::new());
+        assert_eq!(texts("update_node() # and a comment; not a command"), vec!["update_node() "]);
+    }
+
+    #[test]
+    fn test_quotes_protect_separators() {
+        assert_eq!(
+            texts(r#"set_log_filter(log_filter="a;b#c\"d;"); update_node()"#),
+            vec![r#"set_log_filter(log_filter="a;b#c\"d;")"#, " update_node()"]
+        );
+    }
+
+    #[test]
+    fn test_statements_track_line_numbers() {
+        let source = "# setup\n\nupdate_node()\nupdate_probe(); reboot_probe()\n";
+        let lines: Vec<(usize, &str)> = statements(source).iter().map(|s| (s.line_number, s.text.trim())).collect();
+        assert_eq!(lines, vec![(3, "update_node()"), (4, "update_probe()"), (4, "reboot_probe()")]);
+    }
+}
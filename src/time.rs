@@ -0,0 +1,349 @@
+use chrono::{DateTime, Days, Local, LocalResult, NaiveDateTime, NaiveTime, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::iso8601;
+use crate::parser::{closest, Span};
+
+/// Why an absolute timestamp could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimestampError {
+    /// Not ISO 8601; `span` is the offending part of the text and `expected` names the form wanted there
+    Malformed { expected: &'static str, span: Span },
+    /// The `[Zone]` suffix does not name an IANA time zone; `start` is the name's byte offset
+    UnknownZone {
+        name: String,
+        suggestion: Option<&'static str>,
+        start: usize,
+    },
+    /// A well-formed timestamp that does not denote a single instant
+    Invalid(&'static str),
+}
+
+/// Parses an absolute timestamp: ISO 8601 (see `iso8601::parse`) with a UTC offset, optionally followed by a
+/// `[Europe/Budapest]` zone, or without an offset in the `[Zone]` or `default_zone`.
+pub fn parse_absolute(text: &str, default_zone: Option<Tz>) -> Result<DateTime<Utc>, TimestampError> {
+    let text = text.trim();
+    let (base, zone) = match text.strip_suffix(']').and_then(|rest| rest.split_once('[')) {
+        Some((base, name)) => {
+            let zone = name.parse::<Tz>().map_err(|_| TimestampError::UnknownZone {
+                name: name.to_string(),
+                suggestion: closest(name, chrono_tz::TZ_VARIANTS.iter().map(|tz| tz.name())),
+                start: base.len() + 1,
+            })?;
+            (base, Some(zone))
+        }
+        None => (text, None),
+    };
+
+    let timestamp = iso8601::parse(base).map_err(|e| TimestampError::Malformed {
+        expected: e.expected,
+        span: e.span,
+    })?;
+    if let Some(offset) = timestamp.offset {
+        let time = timestamp.local.and_local_timezone(offset).single().ok_or(TimestampError::Invalid("is out of range"))?;
+        if let Some(zone) = zone {
+            if zone.offset_from_utc_datetime(&time.naive_utc()).fix() != offset {
+                return Err(TimestampError::Invalid("UTC offset does not match the time zone at that time"));
+            }
+        }
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let zone = zone.or(default_zone).ok_or(TimestampError::Invalid(
+        "has no UTC offset; add one (+01:00), a zone ([Europe/Budapest]) or set timezone in the config file",
+    ))?;
+    resolve_local(timestamp.local, Some(zone)).map_err(TimestampError::Invalid)
+}
+
+/// Converts a wall-clock time in `zone` (the system zone if `None`) to UTC, refusing times
+/// that a daylight saving change skips or repeats.
+pub fn resolve_local(naive: NaiveDateTime, zone: Option<Tz>) -> Result<DateTime<Utc>, &'static str> {
+    let resolved = match zone {
+        Some(zone) => zone.from_local_datetime(&naive).map(|time| time.with_timezone(&Utc)),
+        None => Local.from_local_datetime(&naive).map(|time| time.with_timezone(&Utc)),
+    };
+    match resolved {
+        LocalResult::Single(time) => Ok(time),
+        LocalResult::Ambiguous(..) => Err("local time occurs twice because daylight saving time ends; add a UTC offset"),
+        LocalResult::None => Err("local time does not exist because daylight saving time starts"),
+    }
+}
+
+/// Parses a duration in seconds: a plain number (`90`), units in descending order
+/// (`1d12h`, `1m30s`) or an ISO 8601 duration (`PT2H`, `P1DT30M`, `P2W`).
+pub fn parse_duration(text: &str) -> Result<u64, &'static str> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Ok(seconds);
+    }
+    if text.starts_with(['P', 'p']) {
+        return parse_iso_duration(&text[1..]);
+    }
+
+    const UNITS: [(char, u64); 4] = [('d', 86_400), ('h', 3_600), ('m', 60), ('s', 1)];
+    let invalid = "must be seconds or a duration like 1m30s, 2h or PT2H";
+    if text.is_empty() {
+        return Err(invalid);
+    }
+    let mut total: u64 = 0;
+    let mut next_unit = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or(invalid)?;
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid)?;
+        let unit = rest[digits..].chars().next().ok_or(invalid)?.to_ascii_lowercase();
+        let idx = UNITS.iter().position(|(u, _)| *u == unit).ok_or(invalid)?;
+        if idx < next_unit {
+            return Err("duration units must appear once each, largest first (d, h, m, s)");
+        }
+        total = value
+            .checked_mul(UNITS[idx].1)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or("duration is too long")?;
+        next_unit = idx + 1;
+        rest = &rest[digits + 1..];
+    }
+    Ok(total)
+}
+
+/// Formats seconds the way `parse_duration` reads them back, largest unit first (`1h30m`).
+pub fn format_duration(seconds: u64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let mut out = String::new();
+    let mut rest = seconds;
+    for (unit, size) in [('d', 86_400), ('h', 3_600), ('m', 60), ('s', 1)] {
+        if rest >= size {
+            out.push_str(&format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+    }
+    out
+}
+
+/// Parses the part of an ISO 8601 duration after `P`. Years and months have no fixed length and are rejected.
+fn parse_iso_duration(text: &str) -> Result<u64, &'static str> {
+    let invalid = "must be an ISO 8601 duration like PT2H, PT1M30S or P1DT12H";
+    let (date, time) = match text.split_once(['T', 't']) {
+        Some((_, "")) => return Err(invalid),
+        Some((date, time)) => (date, time),
+        None => (text, ""),
+    };
+    if date.is_empty() && time.is_empty() {
+        return Err(invalid);
+    }
+
+    let mut total: u64 = 0;
+    for (part, units) in [(date, &[('W', 604_800), ('D', 86_400)][..]), (time, &[('H', 3_600), ('M', 60), ('S', 1)][..])] {
+        let mut next_unit = 0;
+        let mut rest = part;
+        while !rest.is_empty() {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or(invalid)?;
+            let value: u64 = rest[..digits].parse().map_err(|_| invalid)?;
+            let unit = rest[digits..].chars().next().ok_or(invalid)?.to_ascii_uppercase();
+            if part == date && matches!(unit, 'Y' | 'M') {
+                return Err("years and months are not supported in durations");
+            }
+            let idx = units.iter().position(|(u, _)| *u == unit).ok_or(invalid)?;
+            if idx < next_unit {
+                return Err(invalid);
+            }
+            total = value
+                .checked_mul(units[idx].1)
+                .and_then(|seconds| total.checked_add(seconds))
+                .ok_or("duration is too long")?;
+            next_unit = idx + 1;
+            rest = &rest[digits + 1..];
+        }
+    }
+    Ok(total)
+}
+
+/// Resolves a relative or natural time expression:
+///
+/// - `now`, `now+30m`, `now - 1h`
+/// - `today 18:00`, `tomorrow 09:00:30` in `zone`, or the system's time zone if `None`
+/// - `+PT2H` or `+2h`, relative to `start` (only where a start time is given)
+///
+/// Returns `None` if `text` is not one of these forms, so that the caller can try an absolute timestamp.
+pub fn parse_relative(
+    text: &str,
+    now: DateTime<Utc>,
+    start: Option<DateTime<Utc>>,
+    zone: Option<Tz>,
+) -> Option<Result<DateTime<Utc>, &'static str>> {
+    let text = text.trim();
+    let lower = text.to_ascii_lowercase();
+
+    if let Some(rest) = lower.strip_prefix("now") {
+        let rest = rest.trim_start();
+        if rest.is_empty() {
+            return Some(Ok(now));
+        }
+        return Some(offset(now, rest));
+    }
+
+    if text.starts_with(['+', '-']) {
+        return Some(match start {
+            Some(start) => offset(start, text),
+            None => Err("only end_time can be relative to start_time"),
+        });
+    }
+
+    let (days, time) = match lower.split_once(char::is_whitespace) {
+        Some(("today", time)) => (0, time),
+        Some(("tomorrow", time)) => (1, time),
+        _ if lower == "today" || lower == "tomorrow" => return Some(Err("needs a time of day, e.g. today 18:00")),
+        _ => return None,
+    };
+    Some(local_time_of_day(now, days, time.trim(), zone))
+}
+
+/// Applies `+<duration>` or `-<duration>` to `base`.
+fn offset(base: DateTime<Utc>, text: &str) -> Result<DateTime<Utc>, &'static str> {
+    let (sign, duration) = if let Some(rest) = text.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = text.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err("expected + or - and a duration, e.g. now+30m");
+    };
+    let seconds = parse_duration(duration)?;
+    let delta = i64::try_from(seconds).ok().and_then(TimeDelta::try_seconds).ok_or("duration is too long")?;
+    base.checked_add_signed(delta * sign).ok_or("time is out of range")
+}
+
+/// `HH:MM[:SS]` on the date of `now` in `zone` plus `days`.
+fn local_time_of_day(now: DateTime<Utc>, days: u64, time: &str, zone: Option<Tz>) -> Result<DateTime<Utc>, &'static str> {
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .map_err(|_| "time of day must be HH:MM or HH:MM:SS")?;
+    let today = match zone {
+        Some(zone) => now.with_timezone(&zone).date_naive(),
+        None => now.with_timezone(&Local).date_naive(),
+    };
+    let date = today.checked_add_days(Days::new(days)).ok_or("time is out of range")?;
+    resolve_local(date.and_time(time), zone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_durations() {
+        for (text, seconds) in [
+            ("90", 90),
+            ("1m30s", 90),
+            ("2h", 7_200),
+            ("1D12H", 129_600),
+            ("45s", 45),
+            ("PT2H", 7_200),
+            ("pt1m30s", 90),
+            ("P1DT12H", 129_600),
+            ("P2W", 1_209_600),
+            ("PT0S", 0),
+        ] {
+            assert_eq!(parse_duration(text), Ok(seconds), "{}", text);
+        }
+
+        for text in ["", "m", "1x", "30s1m", "1h1h", "1.5h", "P", "PT", "P1Y", "P1M", "PT1H2", "-5m"] {
+            assert!(parse_duration(text).is_err(), "{}", text);
+        }
+        assert_eq!(parse_duration("P3M"), Err("years and months are not supported in durations"));
+
+        for (seconds, text) in [(0, "0s"), (45, "45s"), (90, "1m30s"), (7_200, "2h"), (129_601, "1d12h1s")] {
+            assert_eq!(format_duration(seconds), text);
+            assert_eq!(parse_duration(text), Ok(seconds));
+        }
+    }
+
+    #[test]
+    fn test_relative_times() {
+        let now = at("2025-10-23T12:00:00Z");
+        let start = at("2025-10-24T08:00:00Z");
+        let resolve = |text| parse_relative(text, now, Some(start), None).unwrap().unwrap();
+
+        assert_eq!(resolve("now"), now);
+        assert_eq!(resolve("NOW+30m"), at("2025-10-23T12:30:00Z"));
+        assert_eq!(resolve("now - PT1H"), at("2025-10-23T11:00:00Z"));
+        assert_eq!(resolve("+PT2H"), at("2025-10-24T10:00:00Z"));
+        assert_eq!(resolve("+1h30m"), at("2025-10-24T09:30:00Z"));
+
+        assert!(parse_relative("2025-10-23T12:00:00Z", now, None, None).is_none());
+        assert_eq!(parse_relative("+2h", now, None, None), Some(Err("only end_time can be relative to start_time")));
+        assert!(parse_relative("now*2", now, None, None).unwrap().is_err());
+        assert!(parse_relative("nowé", now, None, None).unwrap().is_err());
+        assert!(parse_relative("now+2x", now, None, None).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_today_and_tomorrow_use_local_time() {
+        let now = at("2025-10-23T12:00:00Z");
+        let local_date = now.with_timezone(&Local).date_naive();
+        let expected = |days: u64, h: u32, m: u32| {
+            let date = local_date.checked_add_days(Days::new(days)).unwrap();
+            Local.from_local_datetime(&date.and_hms_opt(h, m, 0).unwrap()).unwrap().with_timezone(&Utc)
+        };
+
+        assert_eq!(parse_relative("today 18:00", now, None, None), Some(Ok(expected(0, 18, 0))));
+        assert_eq!(parse_relative("Tomorrow  09:00", now, None, None), Some(Ok(expected(1, 9, 0))));
+        assert!(parse_relative("today", now, None, None).unwrap().is_err());
+        assert!(parse_relative("tomorrow 25:00", now, None, None).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_absolute_with_zones() {
+        use chrono_tz::Europe::Budapest;
+
+        // Summer time (UTC+2), then winter time (UTC+1)
+        assert_eq!(parse_absolute("2026-07-01T15:30[Europe/Budapest]", None), Ok(at("2026-07-01T13:30:00Z")));
+        assert_eq!(parse_absolute("2026-12-01T15:30:00[Europe/Budapest]", None), Ok(at("2026-12-01T14:30:00Z")));
+        assert_eq!(parse_absolute("2026-07-01T15:30+02:00[Europe/Budapest]", None), Ok(at("2026-07-01T13:30:00Z")));
+        assert_eq!(
+            parse_absolute("2026-07-01T15:30+01:00[Europe/Budapest]", None),
+            Err(TimestampError::Invalid("UTC offset does not match the time zone at that time"))
+        );
+
+        assert_eq!(parse_absolute("2026-07-01T15:30", Some(Budapest)), Ok(at("2026-07-01T13:30:00Z")));
+        assert_eq!(parse_absolute("2026-07-01T15:30:00Z", Some(Budapest)), Ok(at("2026-07-01T15:30:00Z")));
+        assert!(matches!(parse_absolute("2026-07-01T15:30", None), Err(TimestampError::Invalid(_))));
+        assert!(matches!(parse_absolute("soon", Some(Budapest)), Err(TimestampError::Malformed { .. })));
+    }
+
+    #[test]
+    fn test_daylight_saving_changes_are_errors() {
+        use chrono_tz::Europe::Budapest;
+
+        // Clocks jump from 02:00 to 03:00 on 2026-03-29 and fall back from 03:00 to 02:00 on 2026-10-25
+        let gap = parse_absolute("2026-03-29T02:30[Europe/Budapest]", None).unwrap_err();
+        assert_eq!(gap, TimestampError::Invalid("local time does not exist because daylight saving time starts"));
+        let overlap = parse_absolute("2026-10-25T02:30", Some(Budapest)).unwrap_err();
+        assert!(matches!(overlap, TimestampError::Invalid(reason) if reason.contains("occurs twice")));
+
+        // An explicit offset picks one of the two instants
+        assert_eq!(parse_absolute("2026-10-25T02:30+01:00[Europe/Budapest]", None), Ok(at("2026-10-25T01:30:00Z")));
+
+        let now = at("2026-10-24T12:00:00Z");
+        assert!(parse_relative("tomorrow 02:30", now, None, Some(Budapest)).unwrap().is_err());
+        assert_eq!(parse_relative("tomorrow 04:00", now, None, Some(Budapest)), Some(Ok(at("2026-10-25T03:00:00Z"))));
+    }
+
+    #[test]
+    fn test_unknown_zone_suggests_name() {
+        let text = "2026-07-01T15:30[europe/budapest]";
+        match parse_absolute(text, None) {
+            Err(TimestampError::UnknownZone { name, suggestion, start }) => {
+                assert_eq!(name, "europe/budapest");
+                assert_eq!(suggestion, Some("Europe/Budapest"));
+                assert_eq!(&text[start..start + name.len()], "europe/budapest");
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring::default_provider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::version::{TLS12, TLS13};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore, SignatureScheme, SupportedProtocolVersion};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::{TlsSettings, TlsVersion};
+
+/// Builds the TLS setup of the connection to the hub from the `[tls]` table: the system's
+/// root certificates plus `ca-files`, the client certificate, the minimum version and the
+/// SPKI pins. Every file is read here, so a broken setting fails before anything is sent.
+/// Trusted CAs are only required for an `https` hub.
+pub fn client_config(settings: &TlsSettings, https: bool) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    // Certificates of the system store that rustls cannot use are skipped
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    client_config_with_roots(settings, roots, https)
+}
+
+fn client_config_with_roots(settings: &TlsSettings, mut roots: RootCertStore, https: bool) -> Result<ClientConfig> {
+    let provider = Arc::new(default_provider());
+    for path in &settings.ca_files {
+        for cert in read_certs(path).with_context(|| format!("Failed to read CA file {}", path.display()))? {
+            roots.add(cert).with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+        }
+    }
+    if roots.is_empty() && https {
+        bail!("No trusted CA certificates; add tls.ca-files");
+    }
+    let pins = settings.spki_pins.iter().map(|pin| parse_pin(pin)).collect::<Result<Vec<_>>>()?;
+
+    let versions: &[&SupportedProtocolVersion] = match settings.min_version {
+        TlsVersion::Tls12 => &[&TLS13, &TLS12],
+        TlsVersion::Tls13 => &[&TLS13],
+    };
+    let builder = ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+    let builder = match pins.is_empty() {
+        // Without any roots this rejects every certificate, which only matters after a redirect to https
+        true => builder.with_root_certificates(roots),
+        false => {
+            let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("No trusted CA certificates to check tls.spki-pins with; add tls.ca-files")?;
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(PinnedVerifier { inner: verifier, pins }))
+        }
+    };
+    match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => {
+            let chain = read_certs(cert).with_context(|| format!("Failed to read client certificate {}", cert.display()))?;
+            let key = PrivateKeyDer::from_pem_file(key).with_context(|| format!("Failed to read client key {}", key.display()))?;
+            builder.with_client_auth_cert(chain, key).context("tls.client-key does not belong to tls.client-cert")
+        }
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => bail!("tls.client-cert and tls.client-key must be given together"),
+    }
+}
+
+/// All certificates of a PEM file; a file without any is an error.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)?.collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        bail!("no certificates found");
+    }
+    Ok(certs)
+}
+
+/// Reads a pin written as "sha256/<base64>", as printed by
+/// `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    pin.strip_prefix("sha256/")
+        .and_then(|hash| BASE64.decode(hash).ok())
+        .and_then(|hash| hash.try_into().ok())
+        .with_context(|| format!("Invalid SPKI pin {:?}: expected sha256/ followed by the base64 SHA-256 of the hub's public key", pin))
+}
+
+/// The SHA-256 of a certificate's DER-encoded SubjectPublicKeyInfo, which is what a pin names.
+fn spki_sha256(cert: &CertificateDer) -> Result<[u8; 32], webpki::Error> {
+    let cert = webpki::EndEntityCert::try_from(cert)?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.subject_public_key_info());
+    Ok(digest.as_ref().try_into().expect("SHA-256 is 32 bytes"))
+}
+
+/// Validates the hub's certificate as usual, then requires its key to match a pin.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+#[derive(Debug)]
+struct PinMismatch;
+
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the hub's public key matches none of tls.spki-pins")
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let hash = spki_sha256(end_entity).map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        if !self.pins.contains(&hash) {
+            // As `Error::Other`, because `CertificateError::Other` is displayed with `Debug`
+            return Err(rustls::Error::Other(OtherError(Arc::new(PinMismatch))));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, ClientError};
+    use crate::config::Config;
+    use crate::parser::Command;
+    use crate::retry::RetryPolicy;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::ServerConfig;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// A private CA with certificates for the hub (127.0.0.1) and for a station, written to
+    /// a directory of its own as `ca.pem`, `hub.pem`/`hub.key` and `station.pem`/`station.key`.
+    struct Pki {
+        dir: PathBuf,
+        hub: CertificateDer<'static>,
+    }
+
+    impl Pki {
+        fn new(name: &str) -> Pki {
+            let dir = std::env::temp_dir().join(format!("moonblokz-tls-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+            std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+            let mut hub = None;
+            for (file, san) in [("hub", "127.0.0.1"), ("station", "station-7.local")] {
+                let key = KeyPair::generate().unwrap();
+                let cert = CertificateParams::new(vec![san.to_string()]).unwrap().signed_by(&key, &ca, &ca_key).unwrap();
+                std::fs::write(dir.join(format!("{}.pem", file)), cert.pem()).unwrap();
+                std::fs::write(dir.join(format!("{}.key", file)), key.serialize_pem()).unwrap();
+                hub.get_or_insert(cert.der().clone());
+            }
+            Pki { dir, hub: hub.unwrap() }
+        }
+
+        fn file(&self, name: &str) -> PathBuf {
+            self.dir.join(name)
+        }
+
+        /// `[tls]` settings that trust the private CA.
+        fn trusting(&self) -> TlsSettings {
+            TlsSettings {
+                ca_files: vec![self.file("ca.pem")],
+                ..Default::default()
+            }
+        }
+
+        /// Stands in for the hub for one connection and returns its URL. `versions` are the
+        /// TLS versions it speaks; with `client_auth` it requires a certificate from the CA.
+        async fn hub(&self, versions: &[&'static SupportedProtocolVersion], client_auth: bool) -> String {
+            let provider = Arc::new(default_provider());
+            let builder = ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions).unwrap();
+            let builder = match client_auth {
+                true => {
+                    let mut roots = RootCertStore::empty();
+                    roots.add(read_certs(&self.file("ca.pem")).unwrap().remove(0)).unwrap();
+                    builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap())
+                }
+                false => builder.with_no_client_auth(),
+            };
+            let chain = read_certs(&self.file("hub.pem")).unwrap();
+            let key = PrivateKeyDer::from_pem_file(self.file("hub.key")).unwrap();
+            let acceptor = TlsAcceptor::from(Arc::new(builder.with_single_cert(chain, key).unwrap()));
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let mut request = [0; 4096];
+                if stream.read(&mut request).await.is_ok() {
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n").await;
+                    let _ = stream.shutdown().await;
+                }
+            });
+            format!("https://{}", addr)
+        }
+    }
+
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn client(hub_url: &str, tls: TlsSettings) -> Result<Client> {
+        let mut config: Config = toml::from_str(&format!("api-key = \"k\"\nhub-url = \"{}\"\n", hub_url)).unwrap();
+        config.retry = RetryPolicy::none();
+        config.tls = tls;
+        Client::new(config)
+    }
+
+    async fn send(hub_url: &str, tls: TlsSettings) -> Result<String, ClientError> {
+        client(hub_url, tls).unwrap().send_command(&Command::UpdateNode { node_id: Some(21.into()) }, |_| {}).await
+    }
+
+    const BOTH: &[&SupportedProtocolVersion] = &[&TLS13, &TLS12];
+
+    #[tokio::test]
+    async fn test_private_ca() {
+        let pki = Pki::new("ca");
+        let err = send(&pki.hub(BOTH, false).await, TlsSettings::default()).await.unwrap_err();
+        assert!(matches!(err, ClientError::Tls(_)), "{:?}", err);
+        assert_eq!(send(&pki.hub(BOTH, false).await, pki.trusting()).await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_spki_pins() {
+        let pki = Pki::new("pins");
+        let pin = format!("sha256/{}", BASE64.encode(spki_sha256(&pki.hub).unwrap()));
+        let other = format!("sha256/{}", BASE64.encode([7; 32]));
+        let pinned = |pins: &[&String]| TlsSettings {
+            spki_pins: pins.iter().map(|pin| pin.to_string()).collect(),
+            ..pki.trusting()
+        };
+        assert_eq!(send(&pki.hub(BOTH, false).await, pinned(&[&other, &pin])).await.unwrap(), "OK");
+        let err = send(&pki.hub(BOTH, false).await, pinned(&[&other])).await.unwrap_err();
+        assert!(matches!(err, ClientError::Tls(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "TLS error talking to hub: other error: the hub's public key matches none of tls.spki-pins");
+        // A pin does not replace validation of the certificate
+        let err = send(&pki.hub(BOTH, false).await, TlsSettings { ca_files: Vec::new(), ..pinned(&[&pin]) }).await.unwrap_err();
+        assert!(matches!(err, ClientError::Tls(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_min_version() {
+        let pki = Pki::new("version");
+        assert_eq!(send(&pki.hub(&[&TLS12], false).await, pki.trusting()).await.unwrap(), "OK");
+        let tls13 = TlsSettings { min_version: TlsVersion::Tls13, ..pki.trusting() };
+        let err = send(&pki.hub(&[&TLS12], false).await, tls13.clone()).await.unwrap_err();
+        assert!(matches!(err, ClientError::Tls(_)), "{:?}", err);
+        assert_eq!(send(&pki.hub(&[&TLS13], false).await, tls13).await.unwrap(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate() {
+        let pki = Pki::new("mtls");
+        let err = send(&pki.hub(&[&TLS12], true).await, pki.trusting()).await.unwrap_err();
+        assert!(matches!(err, ClientError::Tls(_)), "{:?}", err);
+        let station = TlsSettings {
+            client_cert: Some(pki.file("station.pem")),
+            client_key: Some(pki.file("station.key")),
+            ..pki.trusting()
+        };
+        assert_eq!(send(&pki.hub(BOTH, true).await, station).await.unwrap(), "OK");
+    }
+
+    #[test]
+    fn test_empty_root_store() {
+        assert!(client_config_with_roots(&TlsSettings::default(), RootCertStore::empty(), false).is_ok());
+        let err = client_config_with_roots(&TlsSettings::default(), RootCertStore::empty(), true).unwrap_err();
+        assert_eq!(err.to_string(), "No trusted CA certificates; add tls.ca-files");
+        let pki = Pki::new("roots");
+        assert!(client_config_with_roots(&pki.trusting(), RootCertStore::empty(), true).is_ok());
+    }
+
+    #[test]
+    fn test_misconfiguration_fails_early() {
+        let pki = Pki::new("config");
+        let with = |settings: TlsSettings| format!("{:#}", client("https://127.0.0.1:1", settings).err().unwrap());
+        let missing = pki.file("missing.pem");
+        assert!(with(TlsSettings { ca_files: vec![missing.clone()], ..Default::default() })
+            .starts_with(&format!("Invalid [tls] settings: Failed to read CA file {}: ", missing.display())));
+        assert_eq!(
+            with(TlsSettings { ca_files: vec![pki.file("hub.key")], ..Default::default() }),
+            format!("Invalid [tls] settings: Failed to read CA file {}: no certificates found", pki.file("hub.key").display())
+        );
+        assert_eq!(
+            with(TlsSettings { client_cert: Some(pki.file("station.pem")), ..pki.trusting() }),
+            "Invalid [tls] settings: tls.client-cert and tls.client-key must be given together"
+        );
+        assert!(with(TlsSettings { client_cert: Some(pki.file("station.pem")), client_key: Some(pki.file("hub.key")), ..pki.trusting() })
+            .starts_with("Invalid [tls] settings: tls.client-key does not belong to tls.client-cert"));
+        for pin in ["sha256/abc", "sha1/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=", "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="] {
+            assert!(with(TlsSettings { spki_pins: vec![pin.to_string()], ..Default::default() })
+                .starts_with(&format!("Invalid [tls] settings: Invalid SPKI pin {:?}", pin)));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::parser::closest;
+
+/// A command understood by the node's USB serial console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbCommand {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Leaves the node unable to run tests until someone intervenes
+    pub dangerous: bool,
+}
+
+/// Known node USB commands. Anything else is sent only after confirmation or with `--force`.
+pub const CATALOG: &[UsbCommand] = &[
+    UsbCommand {
+        name: "/LT",
+        description: "Set the log level to TRACE",
+        dangerous: false,
+    },
+    UsbCommand {
+        name: "/LD",
+        description: "Set the log level to DEBUG",
+        dangerous: false,
+    },
+    UsbCommand {
+        name: "/LI",
+        description: "Set the log level to INFO",
+        dangerous: false,
+    },
+    UsbCommand {
+        name: "/LW",
+        description: "Set the log level to WARN",
+        dangerous: false,
+    },
+    UsbCommand {
+        name: "/LE",
+        description: "Set the log level to ERROR",
+        dangerous: false,
+    },
+    UsbCommand {
+        name: "/BS",
+        description: "Reboot into the bootloader; the node stops running until it is flashed or power-cycled",
+        dangerous: true,
+    },
+];
+
+/// Looks up a command exactly as it will be sent.
+pub fn lookup(command: &str) -> Option<&'static UsbCommand> {
+    CATALOG.iter().find(|entry| entry.name == command)
+}
+
+/// Catalog entries starting with `prefix`, ignoring case.
+pub fn completions(prefix: &str) -> Vec<&'static UsbCommand> {
+    CATALOG
+        .iter()
+        .filter(|entry| entry.name.len() >= prefix.len() && entry.name[..prefix.len()].eq_ignore_ascii_case(prefix))
+        .collect()
+}
+
+/// Why a USB command needs confirmation before it is sent, or `None` if it is safe.
+pub fn caution(command: &str) -> Option<String> {
+    match lookup(command) {
+        Some(entry) if entry.dangerous => Some(format!("{} is dangerous: {}", entry.name, entry.description)),
+        Some(_) => None,
+        None => {
+            let mut reason = format!("{} is not a known USB command", command);
+            let candidates = completions(command);
+            if !candidates.is_empty() {
+                let names: Vec<&str> = candidates.iter().map(|entry| entry.name).collect();
+                reason.push_str(&format!(" (known commands starting with {}: {})", command, names.join(", ")));
+            } else if let Some(suggestion) = closest(command, CATALOG.iter().map(|entry| entry.name)) {
+                reason.push_str(&format!(" (did you mean {}?)", suggestion));
+            }
+            Some(reason)
+        }
+    }
+}
+
+/// Formats catalog entries as a table for `usb_commands`.
+pub fn format_catalog(entries: &[&UsbCommand]) -> String {
+    entries
+        .iter()
+        .map(|entry| {
+            let flag = if entry.dangerous { "  [dangerous]" } else { "" };
+            format!("{:<6} {}{}", entry.name, entry.description, flag)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_and_completions() {
+        assert_eq!(lookup("/LT").unwrap().description, "Set the log level to TRACE");
+        assert_eq!(lookup("/lt"), None);
+        assert_eq!(lookup("/LT "), None);
+
+        let names = |prefix| completions(prefix).iter().map(|entry| entry.name).collect::<Vec<_>>();
+        assert_eq!(names("/L"), ["/LT", "/LD", "/LI", "/LW", "/LE"]);
+        assert_eq!(names("/b"), ["/BS"]);
+        assert_eq!(names("").len(), CATALOG.len());
+        assert!(names("/X").is_empty());
+    }
+
+    #[test]
+    fn test_caution() {
+        assert_eq!(caution("/LD"), None);
+        assert_eq!(
+            caution("/BS").unwrap(),
+            "/BS is dangerous: Reboot into the bootloader; the node stops running until it is flashed or power-cycled"
+        );
+        assert_eq!(caution("/L").unwrap(), "/L is not a known USB command (known commands starting with /L: /LT, /LD, /LI, /LW, /LE)");
+        assert_eq!(caution("/lw").unwrap(), "/lw is not a known USB command (known commands starting with /lw: /LW)");
+        assert_eq!(caution("/LX").unwrap(), "/LX is not a known USB command (did you mean /LT?)");
+        assert_eq!(caution("reset please").unwrap(), "reset please is not a known USB command");
+    }
+}
@@ -0,0 +1,750 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::nodes::NodeSelector;
+use crate::parser::{ArgValue, Command};
+use crate::registry::{CommandSpec, ParamType, Registry, Target};
+
+/// Body of a `POST /command` request to the hub.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "command", content = "parameters", rename_all = "snake_case")]
+pub enum WireCommand {
+    /// Set the reporting schedule of a probe, or of all probes
+    SetUpdateInterval(UpdateIntervalParams),
+    /// Set the log verbosity of a node
+    SetLogLevel(LogLevelParams),
+    /// Set the log filter of a node
+    SetLogFilter(LogFilterParams),
+    /// Send a raw USB command to a node
+    RunCommand(RunCommandParams),
+    /// Update the node firmware
+    UpdateNode(NodeParams),
+    /// Update the probe software
+    UpdateProbe(NodeParams),
+    /// Reboot the probe
+    RebootProbe(NodeParams),
+    /// Start a measurement sequence on a node
+    StartMeasurement(MeasurementParams),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateIntervalParams {
+    /// Target node; all probes if absent
+    #[serde(rename = "node id", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<u32>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub start_time: DateTime<Utc>,
+    #[serde(with = "rfc3339")]
+    #[schemars(with = "DateTime<Utc>")]
+    pub end_time: DateTime<Utc>,
+    /// Seconds between reports inside the window
+    pub active_period: u64,
+    /// Seconds between reports outside the window
+    pub inactive_period: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LogLevelParams {
+    /// Target node; all nodes if absent
+    #[serde(rename = "node id", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<u32>,
+    pub log_level: LogLevel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LogFilterParams {
+    /// Target node; all nodes if absent
+    #[serde(rename = "node id", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<u32>,
+    /// Substring a log line must contain to be forwarded
+    pub log_filter: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RunCommandParams {
+    /// Target node; all nodes if absent
+    #[serde(rename = "node id", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<u32>,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NodeParams {
+    /// Target node; all nodes if absent
+    #[serde(rename = "node id", default, skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MeasurementParams {
+    #[serde(rename = "node id")]
+    pub node_id: u32,
+    pub sequence: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [LogLevel::Trace, LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error]
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Timestamps are sent as `to_rfc3339()` output, e.g. `2025-10-23T14:30:00+00:00`.
+mod rfc3339 {
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&text)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(|e| D::Error::custom(format!("invalid RFC 3339 timestamp {}: {}", text, e)))
+    }
+}
+
+/// Parses and validates a JSON payload written in `dialect`. Commands that `registry` adds to
+/// the built-in ones are checked against their entry.
+pub fn command_from_json(text: &str, dialect: Dialect, registry: &'static Registry) -> Result<Command> {
+    let value: Value = serde_json::from_str(text).context("Invalid command JSON")?;
+    let payload = dialect.decode(value)?;
+    let name = payload.get("command").and_then(Value::as_str).unwrap_or_default();
+    let custom = registry
+        .commands
+        .iter()
+        .find(|spec| spec.name == name && Registry::builtin().find(name).is_none());
+    match custom {
+        Some(spec) => custom_from_json(spec, payload.get("parameters")).context("Invalid command JSON"),
+        None => Ok(Command::from(serde_json::from_value::<WireCommand>(payload).context("Invalid command JSON")?)),
+    }
+}
+
+/// Checks that a `raw(...)` or `--raw-json` payload has the shape of a hub request body:
+/// an object with a non-empty `command` string and a `parameters` object, and nothing else.
+/// What the command and its parameters mean is left to the hub.
+pub fn check_raw(payload: &Value) -> Result<(), String> {
+    let Value::Object(fields) = payload else {
+        return Err(format!("expected an object, found {}", describe(payload)));
+    };
+    match fields.get("command") {
+        Some(Value::String(name)) if !name.trim().is_empty() => {}
+        Some(Value::String(_)) => return Err("command must not be empty".to_string()),
+        Some(other) => return Err(format!("command must be a string, found {}", describe(other))),
+        None => return Err("missing field `command`".to_string()),
+    }
+    match fields.get("parameters") {
+        Some(Value::Object(_)) => {}
+        Some(other) => return Err(format!("parameters must be an object, found {}", describe(other))),
+        None => return Err("missing field `parameters`".to_string()),
+    }
+    if let Some(key) = fields.keys().find(|key| *key != "command" && *key != "parameters") {
+        return Err(format!("unknown field `{}`, expected `command` and `parameters`", key));
+    }
+    Ok(())
+}
+
+fn describe(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Reads a `--raw-json` payload. Only its shape is checked, see `check_raw`.
+pub fn raw_from_json(text: &str) -> Result<Command> {
+    let payload: Value = serde_json::from_str(text).context("Invalid raw JSON")?;
+    check_raw(&payload).map_err(|reason| anyhow!(reason)).context("Invalid raw JSON")?;
+    Ok(Command::Raw { payload })
+}
+
+/// The request body for a command from the `commands-file`: `"node id"` plus the parameters
+/// in registry order, with timestamps in RFC 3339 and durations in seconds.
+pub fn custom_to_json(name: &str, node_id: Option<&NodeSelector>, params: &[(&'static str, ArgValue)]) -> Result<Value> {
+    let mut parameters = Map::new();
+    if let Some(nodes) = node_id {
+        parameters.insert("node id".to_string(), single_node(nodes)?.into());
+    }
+    for (key, value) in params {
+        let value = match value {
+            ArgValue::Integer(number) | ArgValue::Duration(number) => Value::from(*number),
+            ArgValue::Text(text) => Value::from(text.as_str()),
+            ArgValue::Timestamp(time) => Value::from(time.to_rfc3339()),
+        };
+        parameters.insert(key.to_string(), value);
+    }
+    Ok(json!({"command": name, "parameters": parameters}))
+}
+
+/// Checks the `parameters` of a hub payload against `spec`, the inverse of `custom_to_json`.
+fn custom_from_json(spec: &'static CommandSpec, parameters: Option<&Value>) -> Result<Command> {
+    let mut parameters = match parameters {
+        Some(Value::Object(parameters)) => parameters.clone(),
+        None => Map::new(),
+        Some(_) => bail!("parameters must be an object"),
+    };
+
+    let node_id = match parameters.remove("node id") {
+        Some(_) if spec.target == Target::None => bail!("{} does not take a \"node id\"", spec.name),
+        Some(value) => Some(
+            value
+                .as_u64()
+                .and_then(|id| u32::try_from(id).ok())
+                .map(NodeSelector::from)
+                .ok_or_else(|| anyhow!("\"node id\" must be a node ID, found {}", value))?,
+        ),
+        None if spec.target == Target::Required => bail!("missing field `node id`"),
+        None => None,
+    };
+
+    let mut params = Vec::new();
+    for param in &spec.params {
+        let Some(value) = parameters.remove(&param.name) else {
+            if param.required {
+                bail!("missing field `{}`", param.name);
+            }
+            continue;
+        };
+        let invalid = |expected: &str| anyhow!("{} must be {}, found {}", param.name, expected, value);
+        let value = match param.kind {
+            ParamType::Integer | ParamType::Duration => {
+                let number = value.as_u64().ok_or_else(|| invalid("a non-negative integer"))?;
+                if param.max.is_some_and(|max| number > max) {
+                    bail!("{} {}", param.name, param.reason);
+                }
+                if param.kind == ParamType::Integer {
+                    ArgValue::Integer(number)
+                } else {
+                    ArgValue::Duration(number)
+                }
+            }
+            ParamType::String | ParamType::Serial => ArgValue::Text(value.as_str().ok_or_else(|| invalid("a string"))?.to_string()),
+            ParamType::Choice => {
+                let text = value.as_str().ok_or_else(|| invalid("a string"))?;
+                let choice = param.values.iter().find(|choice| choice.eq_ignore_ascii_case(text));
+                ArgValue::Text(choice.ok_or_else(|| anyhow!("{} {}", param.name, param.reason))?.clone())
+            }
+            ParamType::Timestamp => {
+                let text = value.as_str().ok_or_else(|| invalid("an RFC 3339 timestamp"))?;
+                let time = DateTime::parse_from_rfc3339(text).map_err(|_| invalid("an RFC 3339 timestamp"))?;
+                ArgValue::Timestamp(time.with_timezone(&Utc))
+            }
+        };
+        params.push((param.name.as_str(), value));
+    }
+    if let Some(key) = parameters.keys().next() {
+        bail!("unknown field `{}` for {}", key, spec.name);
+    }
+
+    Ok(Command::Custom {
+        name: &spec.name,
+        node_id,
+        params,
+    })
+}
+
+/// Naming scheme of the JSON payloads a hub build accepts.
+///
+/// `WireCommand` serializes to the `hub` dialect; the others rename command names and
+/// parameter keys on the way out (`encode`) and back in (`decode`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dialect {
+    /// `"node id"`, `log_level`, `log_filter`, `run_command` (what the hub accepts today)
+    #[default]
+    Hub,
+    /// The spec's /command section: `node_id`, and `command` instead of `run_command`
+    Spec,
+    /// The spec's probe command table: `node_id`, `level`, `set_filter` and `value`
+    Probe,
+}
+
+impl std::fmt::Display for Dialect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Dialect::Hub => "hub",
+            Dialect::Spec => "spec",
+            Dialect::Probe => "probe",
+        })
+    }
+}
+
+/// `(hub name, dialect name)` pairs.
+type CommandRenames = &'static [(&'static str, &'static str)];
+/// `(hub command or "*" for every command, hub key, dialect key)` triples.
+type KeyRenames = &'static [(&'static str, &'static str, &'static str)];
+
+impl Dialect {
+    fn renames(self) -> (CommandRenames, KeyRenames) {
+        match self {
+            Dialect::Hub => (&[], &[]),
+            Dialect::Spec => (&[("run_command", "command")], &[("*", "node id", "node_id")]),
+            Dialect::Probe => (
+                &[("set_log_filter", "set_filter")],
+                &[
+                    ("*", "node id", "node_id"),
+                    ("set_log_level", "log_level", "level"),
+                    ("set_log_filter", "log_filter", "value"),
+                    ("run_command", "command", "value"),
+                ],
+            ),
+        }
+    }
+
+    /// Rewrites a `hub` payload into this dialect.
+    pub fn encode(self, payload: Value) -> Value {
+        self.rename(payload, true)
+    }
+
+    /// Rewrites a payload in this dialect into the `hub` dialect. Hub names that this dialect
+    /// spells differently are rejected; other unknown names are left for deserializing to report.
+    pub fn decode(self, payload: Value) -> Result<Value> {
+        let (commands, keys) = self.renames();
+        let name = payload.get("command").and_then(Value::as_str).unwrap_or_default();
+        if let Some((hub, dialect)) = commands.iter().find(|(hub, _)| *hub == name) {
+            bail!("{} is called {} in the {} dialect", hub, dialect, self);
+        }
+        let hub_name = commands.iter().find(|(_, d)| *d == name).map_or(name, |(h, _)| h);
+        if let Some(Value::Object(params)) = payload.get("parameters") {
+            for &(command, hub, dialect) in keys {
+                if (command == "*" || command == hub_name) && params.contains_key(hub) {
+                    bail!("Parameter {:?} is called {:?} in the {} dialect", hub, dialect, self);
+                }
+            }
+        }
+        Ok(self.rename(payload, false))
+    }
+
+    fn rename(self, mut payload: Value, encode: bool) -> Value {
+        let (commands, keys) = self.renames();
+        let pick = |hub: &'static str, dialect: &'static str| if encode { (hub, dialect) } else { (dialect, hub) };
+
+        let Some(name) = payload.get("command").and_then(Value::as_str).map(str::to_string) else {
+            return payload;
+        };
+        let hub_name = if encode {
+            name.clone()
+        } else {
+            commands.iter().find(|(_, d)| *d == name).map_or(name.clone(), |(h, _)| h.to_string())
+        };
+
+        if let Some(Value::Object(params)) = payload.get_mut("parameters") {
+            let mut renamed = Map::new();
+            for (key, value) in std::mem::take(params) {
+                let key = keys
+                    .iter()
+                    .filter(|(command, _, _)| *command == "*" || *command == hub_name)
+                    .map(|&(_, hub, dialect)| pick(hub, dialect))
+                    .find(|(from, _)| *from == key)
+                    .map_or(key, |(_, to)| to.to_string());
+                renamed.insert(key, value);
+            }
+            *params = renamed;
+        }
+
+        if let Some((_, to)) = commands.iter().map(|&(hub, dialect)| pick(hub, dialect)).find(|(from, _)| *from == name) {
+            payload["command"] = Value::from(to);
+        }
+        payload
+    }
+}
+
+/// JSON Schema of the `/command` request body.
+pub fn schema() -> RootSchema {
+    schema_for!(WireCommand)
+}
+
+impl TryFrom<&Command> for WireCommand {
+    type Error = anyhow::Error;
+
+    fn try_from(command: &Command) -> Result<Self> {
+        let node = |node_id: &Option<NodeSelector>| node_id.as_ref().map(single_node).transpose();
+
+        Ok(match command {
+            Command::SetUpdateInterval {
+                node_id,
+                start_time,
+                end_time,
+                active_period,
+                inactive_period,
+            } => WireCommand::SetUpdateInterval(UpdateIntervalParams {
+                node_id: node(node_id)?,
+                start_time: *start_time,
+                end_time: *end_time,
+                active_period: *active_period,
+                inactive_period: *inactive_period,
+            }),
+            Command::SetLogLevel { node_id, log_level } => WireCommand::SetLogLevel(LogLevelParams {
+                node_id: node(node_id)?,
+                log_level: LogLevel::from_name(log_level).ok_or_else(|| anyhow!("Invalid log level {}", log_level))?,
+            }),
+            Command::SetLogFilter { node_id, log_filter } => WireCommand::SetLogFilter(LogFilterParams {
+                node_id: node(node_id)?,
+                log_filter: log_filter.clone(),
+            }),
+            Command::Command { node_id, command } => WireCommand::RunCommand(RunCommandParams {
+                node_id: node(node_id)?,
+                command: command.clone(),
+            }),
+            Command::UpdateNode { node_id } => WireCommand::UpdateNode(NodeParams { node_id: node(node_id)? }),
+            Command::UpdateProbe { node_id } => WireCommand::UpdateProbe(NodeParams { node_id: node(node_id)? }),
+            Command::RebootProbe { node_id } => WireCommand::RebootProbe(NodeParams { node_id: node(node_id)? }),
+            Command::StartMeasurement { node_id, sequence } => WireCommand::StartMeasurement(MeasurementParams {
+                node_id: single_node(node_id)?,
+                sequence: *sequence,
+            }),
+            Command::PreviewFilter { .. } => {
+                return Err(anyhow!("preview_filter command is handled locally and cannot be converted to JSON"))
+            }
+            Command::UsbCommands { .. } => {
+                return Err(anyhow!("usb_commands command is handled locally and cannot be converted to JSON"))
+            }
+            Command::Nodes { .. } => return Err(anyhow!("nodes command is handled locally and cannot be converted to JSON")),
+            Command::Help { .. } => return Err(anyhow!("help command is handled locally and cannot be converted to JSON")),
+            Command::Custom { name, .. } => return Err(anyhow!("{} is not a built-in hub command; use custom_to_json", name)),
+            Command::Raw { .. } => return Err(anyhow!("raw payloads are sent as written and have no wire type")),
+            Command::Quit => return Err(anyhow!("Quit command cannot be converted to JSON")),
+        })
+    }
+}
+
+impl From<WireCommand> for Command {
+    fn from(wire: WireCommand) -> Self {
+        let node = |node_id: Option<u32>| node_id.map(NodeSelector::from);
+
+        match wire {
+            WireCommand::SetUpdateInterval(p) => Command::SetUpdateInterval {
+                node_id: node(p.node_id),
+                start_time: p.start_time,
+                end_time: p.end_time,
+                active_period: p.active_period,
+                inactive_period: p.inactive_period,
+            },
+            WireCommand::SetLogLevel(p) => Command::SetLogLevel {
+                node_id: node(p.node_id),
+                log_level: p.log_level.as_str().to_string(),
+            },
+            WireCommand::SetLogFilter(p) => Command::SetLogFilter {
+                node_id: node(p.node_id),
+                log_filter: p.log_filter,
+            },
+            WireCommand::RunCommand(p) => Command::Command {
+                node_id: node(p.node_id),
+                command: p.command,
+            },
+            WireCommand::UpdateNode(p) => Command::UpdateNode { node_id: node(p.node_id) },
+            WireCommand::UpdateProbe(p) => Command::UpdateProbe { node_id: node(p.node_id) },
+            WireCommand::RebootProbe(p) => Command::RebootProbe { node_id: node(p.node_id) },
+            WireCommand::StartMeasurement(p) => Command::StartMeasurement {
+                node_id: p.node_id.into(),
+                sequence: p.sequence,
+            },
+        }
+    }
+}
+
+/// The single node ID sent as `"node id"`; multi-node commands must be expanded first.
+fn single_node(nodes: &NodeSelector) -> Result<u32> {
+    nodes
+        .single()
+        .ok_or_else(|| anyhow!("Command targets several nodes and must be expanded before sending"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_command_with, ParseContext};
+    use serde_json::{json, Value};
+
+    fn from_json(text: &str, dialect: Dialect) -> Result<Command> {
+        command_from_json(text, dialect, Registry::builtin())
+    }
+
+    fn to_json(input: &str) -> Value {
+        parse_command_with(input, &ParseContext::default()).unwrap().to_json().unwrap()
+    }
+
+    #[test]
+    fn test_payloads_match_hub_format() {
+        assert_eq!(
+            to_json("set_update_interval(start_time=2025-10-23T15:30:00+01:00, end_time=2025-10-23T18:00:00Z, active_period=60, inactive_period=300)"),
+            json!({
+                "command": "set_update_interval",
+                "parameters": {
+                    "start_time": "2025-10-23T14:30:00+00:00",
+                    "end_time": "2025-10-23T18:00:00+00:00",
+                    "active_period": 60,
+                    "inactive_period": 300,
+                },
+            })
+        );
+        assert_eq!(
+            to_json("set_update_interval(node_id=21, start_time=2025-10-23T15:30:00Z, end_time=2025-10-23T18:00:00Z, active_period=10, inactive_period=300)"),
+            json!({
+                "command": "set_update_interval",
+                "parameters": {
+                    "node id": 21,
+                    "start_time": "2025-10-23T15:30:00+00:00",
+                    "end_time": "2025-10-23T18:00:00+00:00",
+                    "active_period": 10,
+                    "inactive_period": 300,
+                },
+            })
+        );
+        assert_eq!(
+            to_json("set_log_level(node_id=21, log_level=debug)"),
+            json!({"command": "set_log_level", "parameters": {"node id": 21, "log_level": "DEBUG"}})
+        );
+        assert_eq!(
+            to_json(r#"set_log_filter(log_filter="[ERROR]")"#),
+            json!({"command": "set_log_filter", "parameters": {"log_filter": "[ERROR]"}})
+        );
+        assert_eq!(
+            to_json("run_command(node_id=5, command=/LT)"),
+            json!({"command": "run_command", "parameters": {"node id": 5, "command": "/LT"}})
+        );
+        assert_eq!(to_json("update_node()"), json!({"command": "update_node", "parameters": {}}));
+        assert_eq!(
+            to_json("reboot_probe(node_id=3)"),
+            json!({"command": "reboot_probe", "parameters": {"node id": 3}})
+        );
+        assert_eq!(
+            to_json("start_measurement(node_id=21, sequence=7)"),
+            json!({"command": "start_measurement", "parameters": {"node id": 21, "sequence": 7}})
+        );
+    }
+
+    #[test]
+    fn test_json_round_trips_to_command() {
+        let input = r#"{"command": "set_log_level", "parameters": {"node id": 21, "log_level": "WARN"}}"#;
+        let command = from_json(input, Dialect::Hub).unwrap();
+        assert_eq!(command.to_string(), "set_log_level(node_id=21, log_level=WARN)");
+        assert_eq!(command.to_json().unwrap(), serde_json::from_str::<Value>(input).unwrap());
+
+        let input = r#"{"command": "set_update_interval", "parameters": {"start_time": "2025-10-23T15:30:00+01:00",
+            "end_time": "2025-10-23T18:00:00Z", "active_period": 60, "inactive_period": 300}}"#;
+        assert!(matches!(from_json(input, Dialect::Hub).unwrap(), Command::SetUpdateInterval { active_period: 60, .. }));
+    }
+
+    #[test]
+    fn test_invalid_payloads_rejected() {
+        let cases = [
+            (r#"{"command": "explode", "parameters": {}}"#, "unknown variant"),
+            (r#"{"command": "update_node", "parameters": {"node_id": 21}}"#, "unknown field"),
+            (r#"{"command": "set_log_level", "parameters": {"log_level": "LOUD"}}"#, "unknown variant"),
+            (r#"{"command": "start_measurement", "parameters": {"sequence": 1}}"#, "missing field"),
+            (r#"{"command": "update_node", "parameters": {"node id": -1}}"#, "invalid value"),
+            (
+                r#"{"command": "set_update_interval", "parameters": {"start_time": "soon", "end_time": "2025-10-23T18:00:00Z", "active_period": 60, "inactive_period": 300}}"#,
+                "invalid RFC 3339 timestamp",
+            ),
+        ];
+        for (input, message) in cases {
+            let err = format!("{:#}", from_json(input, Dialect::Hub).unwrap_err());
+            assert!(err.contains(message), "{}: {}", input, err);
+        }
+    }
+
+    #[test]
+    fn test_schema_lists_every_command() {
+        let schema = serde_json::to_string(&schema()).unwrap();
+        for name in [
+            "set_update_interval",
+            "set_log_level",
+            "set_log_filter",
+            "run_command",
+            "update_node",
+            "update_probe",
+            "reboot_probe",
+            "start_measurement",
+            "node id",
+        ] {
+            assert!(schema.contains(&format!("\"{}\"", name)), "{}", name);
+        }
+    }
+
+    /// One example of every command, with node IDs where the command takes them.
+    const GOLDEN_COMMANDS: &[&str] = &[
+        "set_update_interval(start_time=2025-10-23T15:30:00+01:00, end_time=2025-10-23T18:00:00Z, active_period=60, inactive_period=300)",
+        "set_log_level(node_id=21, log_level=DEBUG)",
+        r#"set_log_filter(node_id=21, log_filter="[ERROR]")"#,
+        r#"run_command(node_id=21, command="/LT")"#,
+        "update_node(node_id=21)",
+        "update_probe()",
+        "reboot_probe(node_id=21)",
+        "start_measurement(node_id=21, sequence=7)",
+    ];
+
+    fn check_golden(dialect: Dialect, expected: &[Value]) {
+        assert_eq!(GOLDEN_COMMANDS.len(), expected.len());
+        for (input, expected) in GOLDEN_COMMANDS.iter().zip(expected) {
+            let encoded = dialect.encode(to_json(input));
+            assert_eq!(&encoded, expected, "{:?} {}", dialect, input);
+
+            let decoded = from_json(&encoded.to_string(), dialect).unwrap();
+            assert_eq!(decoded.to_json().unwrap(), to_json(input), "{:?} {}", dialect, input);
+        }
+    }
+
+    #[test]
+    fn test_golden_hub_dialect() {
+        check_golden(
+            Dialect::Hub,
+            &[
+                json!({"command": "set_update_interval", "parameters": {"start_time": "2025-10-23T14:30:00+00:00", "end_time": "2025-10-23T18:00:00+00:00", "active_period": 60, "inactive_period": 300}}),
+                json!({"command": "set_log_level", "parameters": {"node id": 21, "log_level": "DEBUG"}}),
+                json!({"command": "set_log_filter", "parameters": {"node id": 21, "log_filter": "[ERROR]"}}),
+                json!({"command": "run_command", "parameters": {"node id": 21, "command": "/LT"}}),
+                json!({"command": "update_node", "parameters": {"node id": 21}}),
+                json!({"command": "update_probe", "parameters": {}}),
+                json!({"command": "reboot_probe", "parameters": {"node id": 21}}),
+                json!({"command": "start_measurement", "parameters": {"node id": 21, "sequence": 7}}),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_golden_spec_dialect() {
+        check_golden(
+            Dialect::Spec,
+            &[
+                json!({"command": "set_update_interval", "parameters": {"start_time": "2025-10-23T14:30:00+00:00", "end_time": "2025-10-23T18:00:00+00:00", "active_period": 60, "inactive_period": 300}}),
+                json!({"command": "set_log_level", "parameters": {"node_id": 21, "log_level": "DEBUG"}}),
+                json!({"command": "set_log_filter", "parameters": {"node_id": 21, "log_filter": "[ERROR]"}}),
+                json!({"command": "command", "parameters": {"node_id": 21, "command": "/LT"}}),
+                json!({"command": "update_node", "parameters": {"node_id": 21}}),
+                json!({"command": "update_probe", "parameters": {}}),
+                json!({"command": "reboot_probe", "parameters": {"node_id": 21}}),
+                json!({"command": "start_measurement", "parameters": {"node_id": 21, "sequence": 7}}),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_golden_probe_dialect() {
+        check_golden(
+            Dialect::Probe,
+            &[
+                json!({"command": "set_update_interval", "parameters": {"start_time": "2025-10-23T14:30:00+00:00", "end_time": "2025-10-23T18:00:00+00:00", "active_period": 60, "inactive_period": 300}}),
+                json!({"command": "set_log_level", "parameters": {"node_id": 21, "level": "DEBUG"}}),
+                json!({"command": "set_filter", "parameters": {"node_id": 21, "value": "[ERROR]"}}),
+                json!({"command": "run_command", "parameters": {"node_id": 21, "value": "/LT"}}),
+                json!({"command": "update_node", "parameters": {"node_id": 21}}),
+                json!({"command": "update_probe", "parameters": {}}),
+                json!({"command": "reboot_probe", "parameters": {"node_id": 21}}),
+                json!({"command": "start_measurement", "parameters": {"node_id": 21, "sequence": 7}}),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_payload_in_wrong_dialect_rejected() {
+        let spec = r#"{"command": "set_log_level", "parameters": {"node_id": 21, "log_level": "DEBUG"}}"#;
+        assert!(from_json(spec, Dialect::Spec).is_ok());
+        let err = format!("{:#}", from_json(spec, Dialect::Hub).unwrap_err());
+        assert!(err.contains("unknown field `node_id`"), "{}", err);
+        let err = from_json(spec, Dialect::Probe).unwrap_err().to_string();
+        assert_eq!(err, r#"Parameter "log_level" is called "level" in the probe dialect"#);
+
+        let hub = r#"{"command": "run_command", "parameters": {"command": "/LT"}}"#;
+        let err = from_json(hub, Dialect::Spec).unwrap_err().to_string();
+        assert_eq!(err, "run_command is called command in the spec dialect");
+    }
+
+    #[test]
+    fn test_raw_payloads() {
+        let cmd = raw_from_json(r#"{"command": "set_radio_power", "parameters": {"node id": 21, "dbm": 14}}"#).unwrap();
+        assert_eq!(cmd.to_json().unwrap()["parameters"]["dbm"], 14);
+
+        let check = |payload: Value| check_raw(&payload).unwrap_err();
+        assert_eq!(check(json!([])), "expected an object, found an array");
+        assert_eq!(check(json!({"parameters": {}})), "missing field `command`");
+        assert_eq!(check(json!({"command": " ", "parameters": {}})), "command must not be empty");
+        assert_eq!(check(json!({"command": 3, "parameters": {}})), "command must be a string, found a number");
+        assert_eq!(check(json!({"command": "x"})), "missing field `parameters`");
+        assert_eq!(check(json!({"command": "x", "parameters": null})), "parameters must be an object, found null");
+        assert_eq!(check(json!({"command": "x", "parameters": {}, "node id": 21})), "unknown field `node id`, expected `command` and `parameters`");
+
+        let err = raw_from_json("{").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid raw JSON");
+    }
+
+    #[test]
+    fn test_registry_command_payloads() {
+        let registry: &'static Registry = Box::leak(Box::new(
+            Registry::with_extra(
+                r#"
+[[command]]
+name = "set_radio_power"
+params = [
+    { name = "dbm", type = "integer", max = 22 },
+    { name = "mode", type = "choice", values = ["LOW", "HIGH"], required = false },
+]
+"#,
+            )
+            .unwrap(),
+        ));
+        let decode = |text: &str, dialect| command_from_json(text, dialect, registry).map_err(|e| format!("{:#}", e));
+
+        let command = decode(r#"{"command": "set_radio_power", "parameters": {"node_id": 21, "dbm": 14, "mode": "high"}}"#, Dialect::Spec).unwrap();
+        assert_eq!(command.to_string(), r#"set_radio_power(node_id=21, dbm=14, mode="HIGH")"#);
+        assert_eq!(
+            Dialect::Spec.encode(command.to_json().unwrap()),
+            json!({"command": "set_radio_power", "parameters": {"node_id": 21, "dbm": 14, "mode": "HIGH"}})
+        );
+        assert!(decode(r#"{"command": "set_radio_power", "parameters": {"dbm": 1}}"#, Dialect::Hub).is_ok());
+        // Built-in commands still go through WireCommand
+        assert!(matches!(decode(r#"{"command": "update_node", "parameters": {}}"#, Dialect::Hub), Ok(Command::UpdateNode { node_id: None })));
+
+        for (parameters, expected) in [
+            (r#"{}"#, "Invalid command JSON: missing field `dbm`"),
+            (r#"{"dbm": "14"}"#, r#"Invalid command JSON: dbm must be a non-negative integer, found "14""#),
+            (r#"{"dbm": 23}"#, "Invalid command JSON: dbm must be at most 22"),
+            (r#"{"dbm": 1, "mode": "max"}"#, "Invalid command JSON: mode must be LOW or HIGH"),
+            (r#"{"dbm": 1, "power": 3}"#, "Invalid command JSON: unknown field `power` for set_radio_power"),
+            (r#"{"node id": -1, "dbm": 1}"#, r#"Invalid command JSON: "node id" must be a node ID, found -1"#),
+        ] {
+            let payload = format!(r#"{{"command": "set_radio_power", "parameters": {}}}"#, parameters);
+            assert_eq!(decode(&payload, Dialect::Hub).unwrap_err(), expected);
+        }
+        // Without the command file the hub payload is unknown
+        assert!(from_json(r#"{"command": "set_radio_power", "parameters": {"dbm": 1}}"#, Dialect::Hub).is_err());
+    }
+}